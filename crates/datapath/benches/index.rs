@@ -0,0 +1,94 @@
+// benches only use some of datapath's dependencies
+#![expect(unused_crate_dependencies)]
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use datapath::{DatapathIndex, Rule};
+use std::hint::black_box;
+
+const DOMAINS: usize = 100_000;
+const TIMESTAMPS: usize = 3;
+
+fn paths() -> impl Iterator<Item = String> {
+	(0..DOMAINS)
+		.flat_map(|d| (0..TIMESTAMPS).map(move |t| format!("web/domain=d{d}.com/ts={t}/data.json")))
+}
+
+fn build_index() -> DatapathIndex {
+	DatapathIndex::new(paths())
+}
+
+const QUERIES: [(&str, &str); 4] = [
+	("exact", "web/domain=d5000.com/ts=1/data.json"),
+	("literal_prefix", "web/domain=d5000.com/**"),
+	("leading_star", "*/domain=d5000.com/ts=1/data.json"),
+	// Every domain matches, so this visits all of them
+	("value_wildcard", "web/domain=*/ts=1/data.json"),
+];
+
+fn query(c: &mut Criterion) {
+	let idx = build_index();
+
+	let mut group = c.benchmark_group("query");
+	for (name, q) in QUERIES {
+		group.bench_function(name, |b| {
+			b.iter(|| {
+				#[expect(clippy::unwrap_used)]
+				idx.query(black_box(q)).unwrap().count()
+			})
+		});
+	}
+
+	group.bench_function("count_prefix", |b| {
+		b.iter(|| {
+			#[expect(clippy::unwrap_used)]
+			idx.query_count(black_box("web/domain=*/**")).unwrap()
+		})
+	});
+
+	group.finish();
+}
+
+/// Check every path against the query's regex,
+/// which is what the index does without a trie.
+/// Compare these with `query` to see what the trie saves.
+fn regex_scan(c: &mut Criterion) {
+	let paths = paths().collect::<Vec<_>>();
+
+	let mut group = c.benchmark_group("regex_scan");
+	for (name, q) in QUERIES {
+		group.bench_function(name, |b| {
+			b.iter(|| {
+				#[expect(clippy::unwrap_used)]
+				let rule = Rule::new(black_box(q)).unwrap();
+				// Queries return owned paths, so clone them here too
+				paths
+					.iter()
+					.filter(|x| rule.is_match(x))
+					.cloned()
+					.collect::<Vec<_>>()
+					.len()
+			})
+		});
+	}
+
+	group.finish();
+}
+
+fn build(c: &mut Criterion) {
+	let mut group = c.benchmark_group("build");
+	group.sample_size(10);
+	group.bench_function("new", |b| b.iter(build_index));
+
+	#[cfg(feature = "rayon")]
+	{
+		let paths = paths().collect::<Vec<_>>();
+		group.bench_function("par_new", |b| {
+			b.iter(|| DatapathIndex::par_new(paths.clone()))
+		});
+	}
+
+	group.finish();
+}
+
+criterion_group!(benches, query, regex_scan, build);
+criterion_main!(benches);
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use tracing::trace;
+
+use crate::{
+	Datapath, DatapathIndex, Rule,
+	index::{query::Matches, trie::EMPTY_NODE},
+};
+
+/// When [DatapathIndex::plan_compaction] compacts files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionPolicy {
+	/// The largest size of a compacted file, in bytes.
+	/// Files this large or larger are never compacted.
+	pub target_size: u64,
+
+	/// Only compact at least this many files at once
+	pub min_files: usize,
+}
+
+impl CompactionPolicy {
+	pub fn new(target_size: u64, min_files: usize) -> Self {
+		Self {
+			target_size,
+			min_files,
+		}
+	}
+}
+
+/// A set of files in one partition to compact into one file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionBatch {
+	/// The partition these files are in
+	pub partition: String,
+
+	/// The paths of the files to compact, in order
+	pub inputs: Vec<String>,
+
+	/// The total size of `inputs`, in bytes
+	pub input_bytes: u64,
+
+	/// A suggested path for the compacted file. This is in `partition`,
+	/// and isn't the path of anything in this index, even paths that don't
+	/// match the query.
+	pub output: String,
+}
+
+impl DatapathIndex {
+	/// Plan how to compact the small files that match `query`.
+	///
+	/// Files are grouped by partition (the path without its last segment),
+	/// and the files in each partition that are smaller than the target size
+	/// are packed, in order, into batches of at most that size. Batches with
+	/// fewer than the minimum number of files are dropped.
+	/// Files without a size are never compacted.
+	///
+	/// The plan only depends on the paths and sizes in this index,
+	/// so the same index always gives the same plan.
+	/// Returns `None` if the query was invalid.
+	pub fn plan_compaction(
+		&self,
+		query: impl Into<String>,
+		policy: &CompactionPolicy,
+	) -> Option<Vec<CompactionBatch>> {
+		let rule = Rule::new(query)?;
+		Some(self.plan_compaction_rule(&rule, policy))
+	}
+
+	/// Like [Self::plan_compaction], but with a precompiled rule
+	pub fn plan_compaction_rule(
+		&self,
+		rule: &Rule,
+		policy: &CompactionPolicy,
+	) -> Vec<CompactionBatch> {
+		self.plan_compaction_parsed(rule, policy, |path| {
+			let (partition, name) = path.rsplit_once('/')?;
+			Some((partition.to_owned(), name.to_owned()))
+		})
+	}
+
+	/// Like [Self::plan_compaction], but only looks at paths that match `path`
+	/// (see [Datapath::from_wildcardable]). Files are grouped by their datapath,
+	/// and their file part may have more than one segment.
+	/// Paths that don't parse as `D` are skipped.
+	///
+	/// Returns `None` if a value in `path` makes an invalid query, like `***`.
+	pub fn plan_compaction_datapath<D: Datapath>(
+		&self,
+		path: D::WildcardableTuple,
+		policy: &CompactionPolicy,
+	) -> Option<Vec<CompactionBatch>> {
+		let rule = Rule::datapath::<D>(path)?;
+
+		Some(self.plan_compaction_parsed(&rule, policy, |path| {
+			let file = D::parse(path)?;
+			(!file.file.is_empty()).then(|| (file.path.to_string(), file.file))
+		}))
+	}
+
+	/// Plan a compaction, using `split` to get the partition and name of each file.
+	/// Files that `split` returns `None` for are skipped.
+	fn plan_compaction_parsed(
+		&self,
+		rule: &Rule,
+		policy: &CompactionPolicy,
+		split: impl Fn(&str) -> Option<(String, String)>,
+	) -> Vec<CompactionBatch> {
+		trace!("DatapathIndex query is {}", rule.pattern());
+
+		// Partitions, in the order we first see them,
+		// with their files that are small enough to compact
+		let mut order: Vec<String> = Vec::new();
+		let mut partitions: HashMap<String, Vec<(String, String, u64)>> = HashMap::new();
+
+		let mut matches = Matches::new(&self.root, self.matcher(rule));
+		while let Some((path, meta)) = matches.next_ref() {
+			let path = path.to_string();
+			let Some((partition, name)) = split(&path) else {
+				continue;
+			};
+
+			let files = match partitions.get_mut(&partition) {
+				Some(x) => x,
+				None => {
+					order.push(partition.clone());
+					partitions.entry(partition).or_default()
+				}
+			};
+
+			if let Some(size) = meta.size
+				&& size < policy.target_size
+			{
+				files.push((path, name, size));
+			}
+		}
+
+		let mut batches = Vec::new();
+		for partition in order {
+			let Some(files) = partitions.remove(&partition) else {
+				continue;
+			};
+
+			// Check every child of this partition, not just the paths we matched
+			let node = self
+				.root
+				.get(&partition.split('/').collect::<Vec<_>>())
+				.unwrap_or(&EMPTY_NODE);
+
+			let mut next_output = 0;
+			for batch in Self::pack(files, policy) {
+				let ext = Self::common_extension(batch.iter().map(|(_, name, _)| name.as_str()));
+
+				// Don't overwrite files that already exist
+				let output = loop {
+					let name = format!("compacted-{next_output:05}{ext}");
+					next_output += 1;
+					if node.children.get(&name).is_none() {
+						break name;
+					}
+				};
+
+				batches.push(CompactionBatch {
+					output: format!("{partition}/{output}"),
+					partition: partition.clone(),
+					input_bytes: batch.iter().map(|(_, _, size)| size).sum(),
+					inputs: batch.into_iter().map(|(path, _, _)| path).collect(),
+				});
+			}
+		}
+
+		return batches;
+	}
+
+	/// Pack `files` into batches of at most `policy.target_size` bytes, in order.
+	/// Batches with fewer than `policy.min_files` files are dropped.
+	fn pack(
+		files: Vec<(String, String, u64)>,
+		policy: &CompactionPolicy,
+	) -> Vec<Vec<(String, String, u64)>> {
+		let min_files = policy.min_files.max(2);
+		if files.len() < min_files {
+			return Vec::new();
+		}
+
+		let mut batches = Vec::new();
+		let mut batch = Vec::new();
+		let mut batch_size = 0;
+		for file in files {
+			if batch_size + file.2 > policy.target_size && !batch.is_empty() {
+				batches.push(std::mem::take(&mut batch));
+				batch_size = 0;
+			}
+
+			batch_size += file.2;
+			batch.push(file);
+		}
+		batches.push(batch);
+
+		batches.retain(|x| x.len() >= min_files);
+		return batches;
+	}
+
+	/// The extension shared by all `names`, with its dot.
+	/// Empty if they don't share one.
+	fn common_extension<'a>(mut names: impl Iterator<Item = &'a str>) -> &'a str {
+		let ext = |name: &'a str| {
+			let name = name.rsplit('/').next().unwrap_or(name);
+			name.rfind('.').map(|i| &name[i..]).unwrap_or("")
+		};
+
+		let Some(first) = names.next().map(ext) else {
+			return "";
+		};
+
+		if names.all(|x| ext(x) == first) {
+			first
+		} else {
+			""
+		}
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod compaction_tests {
+	use super::*;
+	use crate::{ObjectMeta, Wildcardable, index::test_paths::CaptureRaw};
+
+	fn object(path: &str, size: Option<u64>) -> (String, ObjectMeta) {
+		let meta = ObjectMeta {
+			size,
+			..Default::default()
+		};
+		(path.to_owned(), meta)
+	}
+
+	fn inputs(batch: &CompactionBatch) -> Vec<&str> {
+		batch
+			.inputs
+			.iter()
+			.map(|x| x.rsplit_once('/').unwrap().1)
+			.collect()
+	}
+
+	#[test]
+	fn plan_compaction() {
+		let mut objects = (0..10)
+			.map(|i| object(&format!("web/ts=1/{i}.parquet"), Some(30)))
+			.collect::<Vec<_>>();
+		objects.extend([
+			object("web/ts=1/big.parquet", Some(500)),
+			object("web/ts=1/unknown.parquet", None),
+			object("web/ts=1/compacted-00000.parquet", Some(50)),
+			object("web/ts=2/a.json", Some(10)),
+			object("web/ts=2/b.parquet", Some(10)),
+			object("web/ts=3/a.json", Some(10)),
+		]);
+		let idx = DatapathIndex::new_with_meta(objects.into_iter());
+
+		let policy = CompactionPolicy::new(100, 2);
+		let plan = idx.plan_compaction("web/**", &policy).unwrap();
+		assert_eq!(plan, idx.plan_compaction("web/**", &policy).unwrap());
+
+		let partitions = plan
+			.iter()
+			.map(|x| x.partition.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			partitions,
+			vec!["web/ts=1"; 4]
+				.into_iter()
+				.chain(["web/ts=2"])
+				.collect::<Vec<_>>()
+		);
+
+		assert_eq!(
+			inputs(&plan[0]),
+			vec!["0.parquet", "1.parquet", "2.parquet"]
+		);
+		assert_eq!(plan[0].input_bytes, 90);
+		assert_eq!(plan[0].output, "web/ts=1/compacted-00001.parquet");
+		assert_eq!(plan[1].output, "web/ts=1/compacted-00002.parquet");
+		assert_eq!(
+			inputs(&plan[3]),
+			vec!["9.parquet", "compacted-00000.parquet"]
+		);
+
+		// Mixed extensions
+		assert_eq!(plan[4].output, "web/ts=2/compacted-00000");
+
+		let plan = idx
+			.plan_compaction("web/**", &CompactionPolicy::new(100, 3))
+			.unwrap();
+		assert_eq!(plan.len(), 3);
+
+		assert!(idx.plan_compaction("web/***", &policy).is_none());
+	}
+
+	#[test]
+	fn output_names() {
+		let objects = vec![
+			object("web/ts=1/data-1.json", Some(10)),
+			object("web/ts=1/data-2.json", Some(10)),
+			object("web/ts=1/compacted-00000.json", Some(1000)),
+			object("web/ts=1/compacted-00001.json/part", Some(10)),
+			object("web/ts=1/compacted-00002.json", Some(10)),
+		];
+
+		// Markers are hidden, so even `web/**` wouldn't match this one
+		let idx =
+			DatapathIndex::new_with_meta(objects.into_iter()).with_marker("compacted-00002.json");
+
+		// Existing paths that the query doesn't match are never overwritten,
+		// and neither are directories
+		let plan = idx
+			.plan_compaction("web/*/data-*", &CompactionPolicy::new(100, 2))
+			.unwrap();
+		assert_eq!(plan.len(), 1);
+		assert_eq!(plan[0].output, "web/ts=1/compacted-00003.json");
+	}
+
+	#[test]
+	fn plan_compaction_datapath() {
+		let objects = vec![
+			object("capture/user_id=a/ts=1/raw/x/1.json", Some(10)),
+			object("capture/user_id=a/ts=1/raw/y.d/2.json", Some(10)),
+			object("capture/user_id=a/ts=2/raw/1.json", Some(10)),
+		];
+		let idx = DatapathIndex::new_with_meta(objects.into_iter());
+
+		let plan = idx
+			.plan_compaction_datapath::<CaptureRaw>(
+				(Wildcardable::Star, Wildcardable::Star),
+				&CompactionPolicy::new(100, 2),
+			)
+			.unwrap();
+		assert_eq!(plan.len(), 1);
+		assert_eq!(plan[0].partition, "capture/user_id=a/ts=1/raw");
+		assert_eq!(plan[0].inputs.len(), 2);
+		assert_eq!(
+			plan[0].output,
+			"capture/user_id=a/ts=1/raw/compacted-00000.json"
+		);
+
+		// Values can make invalid queries
+		let plan = idx.plan_compaction_datapath::<CaptureRaw>(
+			(Wildcardable::Value("***".to_owned()), Wildcardable::Star),
+			&CompactionPolicy::new(100, 2),
+		);
+		assert!(plan.is_none());
+	}
+}
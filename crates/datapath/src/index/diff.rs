@@ -0,0 +1,219 @@
+use std::cmp::Ordering;
+use tracing::trace;
+
+use crate::{
+	DatapathIndex, ObjectMeta, Rule,
+	index::{
+		query::{Matcher, StateSet},
+		trie::{EMPTY_NODE, Node},
+	},
+};
+
+/// The differences between two [DatapathIndex]es.
+/// All paths are sorted segment-by-segment, like query results.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexDiff {
+	/// Paths that only exist in the new index
+	pub added: Vec<String>,
+
+	/// Paths that only exist in the old index
+	pub removed: Vec<String>,
+
+	/// Paths that exist in both indices, but with a different size or ETag.
+	/// Fields that either index doesn't know aren't compared.
+	pub changed: Vec<String>,
+}
+
+impl IndexDiff {
+	/// Returns `true` if both indices held the same paths
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+	}
+}
+
+/// Returns `true` if two objects with the same path differ
+fn is_changed(old: &ObjectMeta, new: &ObjectMeta) -> bool {
+	let size = matches!((old.size, new.size), (Some(a), Some(b)) if a != b);
+	let etag = matches!((&old.etag, &new.etag), (Some(a), Some(b)) if a != b);
+	size || etag
+}
+
+impl DatapathIndex {
+	/// Compare this index with a newer index of the same data.
+	///
+	/// This walks both indices side by side, so shared
+	/// prefixes are only visited once. Indices don't share nodes,
+	/// even when one was cloned from the other, so identical subtrees
+	/// can't be skipped: a diff takes time proportional to the size of
+	/// both indices, however few paths changed. To compare part of
+	/// two indices, use [Self::diff_rule] with a literal prefix.
+	pub fn diff(&self, new: &Self) -> IndexDiff {
+		#[expect(clippy::unwrap_used)]
+		let rule = Rule::new("**").unwrap();
+		self.diff_rule(new, &rule)
+	}
+
+	/// Like [Self::diff], but only compare paths that match `rule`
+	pub fn diff_rule(&self, new: &Self, rule: &Rule) -> IndexDiff {
+		trace!("DatapathIndex diff query is {}", rule.pattern());
+
+		let matcher = Matcher::new(rule);
+		let mut diff = IndexDiff::default();
+		diff_nodes(
+			&matcher,
+			&self.root,
+			&new.root,
+			matcher.start(),
+			&mut Vec::new(),
+			&mut diff,
+		);
+		diff
+	}
+}
+
+/// Diff the children of `old` and `new`, which are at the same path
+fn diff_nodes<'a>(
+	matcher: &Matcher<&Rule>,
+	old: &'a Node,
+	new: &'a Node,
+	states: StateSet,
+	path: &mut Vec<&'a str>,
+	diff: &mut IndexDiff,
+) {
+	let mut old_children = matcher.children(old, states);
+	let mut new_children = matcher.children(new, states);
+	let mut next_old = matcher.next_child(&mut old_children);
+	let mut next_new = matcher.next_child(&mut new_children);
+
+	loop {
+		// Children are sorted, so we can merge them
+		let (seg, old_child, new_child) = match (next_old, next_new) {
+			(None, None) => return,
+			(Some((seg, o)), None) => (seg, Some(o), None),
+			(None, Some((seg, n))) => (seg, None, Some(n)),
+			(Some((a, o)), Some((b, n))) => match a.cmp(b) {
+				Ordering::Less => (a, Some(o), None),
+				Ordering::Greater => (b, None, Some(n)),
+				Ordering::Equal => (a, Some(o), Some(n)),
+			},
+		};
+
+		if old_child.is_some() {
+			next_old = matcher.next_child(&mut old_children);
+		}
+		if new_child.is_some() {
+			next_new = matcher.next_child(&mut new_children);
+		}
+
+		// A path that only exists on one side is diffed against nothing
+		let old_child = old_child.unwrap_or(&EMPTY_NODE);
+		let new_child = new_child.unwrap_or(&EMPTY_NODE);
+
+		let next = matcher.step(states, seg);
+		if next.is_empty() {
+			continue;
+		}
+
+		path.push(seg);
+
+		if matcher.accepts(next) && matcher.verify(path) {
+			match (old_child.object(), new_child.object()) {
+				(None, None) => {}
+				(Some(_), None) => diff.removed.push(path.join("/")),
+				(None, Some(_)) => diff.added.push(path.join("/")),
+				(Some(a), Some(b)) => {
+					if is_changed(a, b) {
+						diff.changed.push(path.join("/"))
+					}
+				}
+			}
+		}
+
+		if matcher.can_continue(next) {
+			diff_nodes(matcher, old_child, new_child, next, path, diff);
+		}
+
+		path.pop();
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod diff_tests {
+	use super::*;
+
+	fn meta(size: u64, etag: &str) -> ObjectMeta {
+		ObjectMeta {
+			size: Some(size),
+			etag: Some(etag.to_owned()),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn added_and_removed() {
+		let old = DatapathIndex::new(
+			["web/ts=1/a.json", "web/ts=2/a.json", "api/ts=1/a.json"].into_iter(),
+		);
+		let new = DatapathIndex::new(
+			["web/ts=2/a.json", "web/ts=3/a.json", "api/ts=1/a.json"].into_iter(),
+		);
+
+		let diff = old.diff(&new);
+		assert_eq!(diff.added, vec!["web/ts=3/a.json"]);
+		assert_eq!(diff.removed, vec!["web/ts=1/a.json"]);
+		assert!(diff.changed.is_empty());
+
+		// Swapping indices swaps added and removed
+		let diff = new.diff(&old);
+		assert_eq!(diff.added, vec!["web/ts=1/a.json"]);
+		assert_eq!(diff.removed, vec!["web/ts=3/a.json"]);
+
+		assert!(old.diff(&old).is_empty());
+		assert!(old.diff(&DatapathIndex::new_empty()).added.is_empty());
+		assert_eq!(old.diff(&DatapathIndex::new_empty()).removed.len(), 3);
+	}
+
+	#[test]
+	fn changed() {
+		let old = DatapathIndex::new_with_meta(
+			[
+				("a/x", meta(1, "e1")),
+				("a/y", meta(2, "e2")),
+				("a/z", meta(3, "e3")),
+				("a/w", ObjectMeta::default()),
+			]
+			.into_iter(),
+		);
+		let new = DatapathIndex::new_with_meta(
+			[
+				("a/x", meta(1, "e1")),
+				("a/y", meta(5, "e2")),
+				("a/z", meta(3, "e4")),
+				("a/w", meta(4, "e5")),
+			]
+			.into_iter(),
+		);
+
+		let diff = old.diff(&new);
+		assert_eq!(diff.changed, vec!["a/y", "a/z"]);
+		assert!(diff.added.is_empty());
+		assert!(diff.removed.is_empty());
+	}
+
+	#[test]
+	fn scoped_to_rule() {
+		let old = DatapathIndex::new(["web/ts=1/a", "api/ts=1/a", "web/ts=1"].into_iter());
+		let new = DatapathIndex::new(["web/ts=2/a", "api/ts=2/a"].into_iter());
+
+		let rule = Rule::new("web/ts=*/*").unwrap();
+		let diff = old.diff_rule(&new, &rule);
+		assert_eq!(diff.added, vec!["web/ts=2/a"]);
+		assert_eq!(diff.removed, vec!["web/ts=1/a"]);
+
+		let rule = Rule::new("**/ts=1").unwrap();
+		let diff = old.diff_rule(&new, &rule);
+		assert!(diff.added.is_empty());
+		assert_eq!(diff.removed, vec!["web/ts=1"]);
+	}
+}
@@ -0,0 +1,144 @@
+use tracing::trace;
+
+use crate::{
+	DatapathIndex, Rule,
+	index::{query::Matches, rule::PatternSegment},
+};
+
+/// How [DatapathIndex::explain] ran a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryPlan {
+	/// The query's pattern
+	pub pattern: String,
+
+	/// The literal segments at the start of the query, joined by `/`.
+	/// These are looked up directly, so only paths that start with
+	/// them are visited. Empty if the query starts with a wildcard.
+	pub prefix: String,
+
+	/// If `true`, the query had too many segments to match one at a time,
+	/// so every path in the index was checked against the query's regex.
+	pub full_scan: bool,
+
+	/// The number of trie nodes visited, including directories
+	pub nodes_visited: usize,
+
+	/// The number of paths visited whose segments matched the query so far.
+	/// Paths whose last segment didn't match aren't counted.
+	pub paths_scanned: usize,
+
+	/// The number of paths that matched the query's segments.
+	/// In a full scan, this is [Self::paths_scanned].
+	pub candidates: usize,
+
+	/// The number of paths that matched the query's regex,
+	/// which is the number of results
+	pub matched: usize,
+}
+
+impl DatapathIndex {
+	/// Run `query`, and describe how it was run.
+	///
+	/// This visits every result of the query, so it is as slow as
+	/// collecting [Self::query]. Use it to see why a query is slow:
+	/// if far more nodes are visited than paths matched, a longer
+	/// literal prefix or a more specific layout may help.
+	///
+	/// Returns `None` if the query was invalid.
+	pub fn explain(&self, query: impl Into<String>) -> Option<QueryPlan> {
+		let rule = Rule::new(query)?;
+		Some(self.explain_rule(&rule))
+	}
+
+	/// Like [Self::explain], but with a precompiled rule
+	pub fn explain_rule(&self, rule: &Rule) -> QueryPlan {
+		trace!("DatapathIndex query is {}", rule.pattern());
+
+		let mut matches = Matches::new(&self.root, self.matcher(rule));
+		let full_scan = matches.is_fallback();
+
+		let mut matched = 0;
+		while matches.next_ref().is_some() {
+			matched += 1;
+		}
+
+		let prefix = match full_scan {
+			true => String::new(),
+			false => rule
+				.segments()
+				.iter()
+				.map_while(|x| match x {
+					PatternSegment::Literal(x) => Some(x.as_str()),
+					_ => None,
+				})
+				.collect::<Vec<_>>()
+				.join("/"),
+		};
+
+		let plan = QueryPlan {
+			pattern: rule.pattern().to_owned(),
+			prefix,
+			full_scan,
+			nodes_visited: matches.visited(),
+			paths_scanned: matches.scanned(),
+			candidates: matches.candidates(),
+			matched,
+		};
+
+		trace!("DatapathIndex query plan is {plan:?}");
+		return plan;
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod explain_tests {
+	use super::*;
+
+	fn index() -> DatapathIndex {
+		let mut paths = (0..10)
+			.map(|i| format!("web/domain=a.com/ts={i}/page.html"))
+			.collect::<Vec<_>>();
+		paths.extend((0..10).map(|i| format!("api/domain=a.com/ts={i}/page.json")));
+		DatapathIndex::new(paths.into_iter())
+	}
+
+	#[test]
+	fn explain() {
+		let idx = index();
+
+		let plan = idx.explain("web/domain=a.com/**").unwrap();
+		assert_eq!(plan.prefix, "web/domain=a.com");
+		assert!(!plan.full_scan);
+		assert_eq!(plan.matched, 10);
+		assert_eq!(plan.paths_scanned, 10);
+		assert_eq!(plan.candidates, 10);
+
+		// `web` and `domain=a.com`, then a `ts` and a file for each path
+		assert_eq!(plan.nodes_visited, 22);
+
+		let plan = idx.explain("*/domain=a.com/ts=1/*.json").unwrap();
+		assert_eq!(plan.prefix, "");
+		assert_eq!(plan.matched, 1);
+		assert_eq!(plan.paths_scanned, 1);
+		assert_eq!(plan.candidates, 1);
+
+		// `page.html` is visited, but doesn't match `*.json`
+		assert_eq!(plan.nodes_visited, 8);
+
+		assert!(idx.explain("web/***").is_none());
+	}
+
+	#[test]
+	fn explain_full_scan() {
+		let idx = index();
+
+		let long = vec!["web"; 70].join("/");
+		let plan = idx.explain(long).unwrap();
+		assert!(plan.full_scan);
+		assert_eq!(plan.prefix, "");
+		assert_eq!(plan.matched, 0);
+		assert_eq!(plan.paths_scanned, idx.len());
+		assert_eq!(plan.candidates, idx.len());
+	}
+}
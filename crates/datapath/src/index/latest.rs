@@ -0,0 +1,317 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+use tracing::trace;
+
+use crate::{
+	Datapath, DatapathFile, DatapathIndex, Rule,
+	index::{
+		query::Matches,
+		range::{compare_values, is_number},
+	},
+};
+
+/// How [DatapathIndex::latest] compares the values of its order key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyOrder {
+	/// Compare values numerically, so `ts=10` is newer than `ts=9`.
+	/// Values that aren't numbers, like `ts=x`, are skipped.
+	/// Values that are the same number are compared as strings,
+	/// so `ts=10.0` is newer than `ts=10`, and each is its own partition.
+	#[default]
+	Numeric,
+
+	/// Always compare values as strings. `ts=9` is newer than `ts=10`.
+	Lexical,
+}
+
+impl KeyOrder {
+	/// Returns `false` if paths with this value should be skipped
+	fn accepts(&self, value: &str) -> bool {
+		match self {
+			Self::Numeric => is_number(value),
+			Self::Lexical => true,
+		}
+	}
+
+	fn compare(&self, a: &str, b: &str) -> Ordering {
+		match self {
+			Self::Numeric => compare_values(a, b),
+			Self::Lexical => a.cmp(b),
+		}
+	}
+}
+
+/// The newest partition of one group, returned by [DatapathIndex::latest]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Latest<P = String> {
+	/// The values of this group's keys, in the order they were given
+	pub group: Vec<String>,
+
+	/// The largest value of the order key in this group
+	pub value: String,
+
+	/// The paths in this group with that value, in order
+	pub paths: Vec<P>,
+}
+
+impl DatapathIndex {
+	/// Find the newest partition of each group of paths that match `query`.
+	///
+	/// Paths are grouped by their values of the `group_by` keys, and each
+	/// group's newest partition has the largest value of `order_key`.
+	/// All paths in that partition are returned, since it may hold many files.
+	/// Paths without one of these keys are skipped,
+	/// as are paths whose value `order` doesn't accept (see [KeyOrder]).
+	///
+	/// For example, `latest("capture/**", &["user_id"], "ts", KeyOrder::Numeric)`
+	/// finds the newest `ts` of each `user_id`.
+	///
+	/// Groups are sorted by their values.
+	/// Returns `None` if the query was invalid.
+	pub fn latest(
+		&self,
+		query: impl Into<String>,
+		group_by: &[&str],
+		order_key: &str,
+		order: KeyOrder,
+	) -> Option<Vec<Latest>> {
+		let rule = Rule::new(query)?;
+		Some(self.latest_rule(&rule, group_by, order_key, order))
+	}
+
+	/// Like [Self::latest], but with a precompiled rule
+	pub fn latest_rule(
+		&self,
+		rule: &Rule,
+		group_by: &[&str],
+		order_key: &str,
+		order: KeyOrder,
+	) -> Vec<Latest> {
+		self.latest_parsed(rule, group_by, order_key, order, |x| Some(x.to_owned()))
+	}
+
+	/// Like [Self::latest], but only looks at paths that match `path`
+	/// (see [Datapath::from_wildcardable]) and parses them as `D`.
+	/// Paths that don't parse are skipped.
+	///
+	/// Returns `None` if a value in `path` makes an invalid query, like `***`.
+	pub fn latest_datapath<D: Datapath>(
+		&self,
+		path: D::WildcardableTuple,
+		group_by: &[&str],
+		order_key: &str,
+		order: KeyOrder,
+	) -> Option<Vec<Latest<DatapathFile<D>>>> {
+		let rule = Rule::datapath::<D>(path)?;
+		Some(self.latest_parsed(&rule, group_by, order_key, order, D::parse))
+	}
+
+	/// Find the newest partition of each group,
+	/// only looking at paths that `parse` returns `Some` for.
+	fn latest_parsed<P>(
+		&self,
+		rule: &Rule,
+		group_by: &[&str],
+		order_key: &str,
+		order: KeyOrder,
+		parse: impl Fn(&str) -> Option<P>,
+	) -> Vec<Latest<P>> {
+		trace!("DatapathIndex query is {}", rule.pattern());
+
+		let mut groups: BTreeMap<Vec<String>, Latest<P>> = BTreeMap::new();
+		let mut matches = Matches::new(&self.root, self.matcher(rule));
+		while let Some((path, _)) = matches.next_ref() {
+			let value_of = |key: &str| {
+				path.segments().iter().find_map(|seg| {
+					seg.split_once('=')
+						.filter(|(k, _)| *k == key)
+						.map(|(_, v)| v)
+				})
+			};
+
+			let Some(group) = group_by
+				.iter()
+				.map(|key| value_of(key).map(|x| x.to_owned()))
+				.collect::<Option<Vec<_>>>()
+			else {
+				continue;
+			};
+
+			let Some(value) = value_of(order_key).filter(|x| order.accepts(x)) else {
+				continue;
+			};
+
+			let newer = groups
+				.get(&group)
+				.map(|x| order.compare(value, &x.value))
+				.unwrap_or(Ordering::Greater);
+			if newer == Ordering::Less {
+				continue;
+			}
+
+			let Some(parsed) = parse(&path.to_string()) else {
+				continue;
+			};
+
+			match groups.get_mut(&group) {
+				Some(latest) if newer == Ordering::Equal => latest.paths.push(parsed),
+				Some(latest) => {
+					latest.value = value.to_owned();
+					latest.paths = vec![parsed];
+				}
+				None => {
+					let latest = Latest {
+						group: group.clone(),
+						value: value.to_owned(),
+						paths: vec![parsed],
+					};
+					groups.insert(group, latest);
+				}
+			}
+		}
+
+		groups.into_values().collect()
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod latest_tests {
+	use super::*;
+	use crate::{Wildcardable, index::test_paths::CaptureRaw};
+
+	fn index() -> DatapathIndex {
+		let paths = vec![
+			"capture/user_id=a/ts=9/raw/1.json",
+			"capture/user_id=a/ts=10/raw/1.json",
+			"capture/user_id=a/ts=10/raw/2.json",
+			"capture/user_id=b/ts=3/raw/1.json",
+			"capture/user_id=b/ts=x/raw/1.json",
+			"capture/user_id=c/raw/1.json",
+			"capture/other/1.json",
+		];
+		DatapathIndex::new(paths.into_iter())
+	}
+
+	#[test]
+	fn latest() {
+		let idx = index();
+
+		let latest = idx
+			.latest("capture/**", &["user_id"], "ts", KeyOrder::Numeric)
+			.unwrap();
+		assert_eq!(
+			latest,
+			vec![
+				Latest {
+					group: vec!["a".to_owned()],
+					value: "10".to_owned(),
+					paths: vec![
+						"capture/user_id=a/ts=10/raw/1.json".to_owned(),
+						"capture/user_id=a/ts=10/raw/2.json".to_owned(),
+					],
+				},
+				// `ts=x` isn't a number, so it is skipped
+				Latest {
+					group: vec!["b".to_owned()],
+					value: "3".to_owned(),
+					paths: vec!["capture/user_id=b/ts=3/raw/1.json".to_owned()],
+				},
+			]
+		);
+
+		let latest = idx
+			.latest("capture/**", &["user_id"], "ts", KeyOrder::Lexical)
+			.unwrap();
+		assert_eq!(latest[0].value, "9");
+		assert_eq!(latest[1].value, "x");
+
+		// With no groups, there is one group
+		let latest = idx
+			.latest("capture/user_id=*/ts=*/**", &[], "ts", KeyOrder::Numeric)
+			.unwrap();
+		assert_eq!(latest.len(), 1);
+		assert_eq!(latest[0].value, "10");
+
+		assert!(
+			idx.latest("capture/***", &[], "ts", KeyOrder::Numeric)
+				.is_none()
+		);
+	}
+
+	#[test]
+	fn latest_ties() {
+		let paths = vec![
+			"capture/user_id=a/ts=10/raw/1.json",
+			"capture/user_id=a/ts=10.0/raw/1.json",
+			"capture/user_id=a/ts=9.5/raw/1.json",
+			"capture/user_id=b/ts=2/raw/1.json",
+			"capture/user_id=b/ts=1a/raw/1.json",
+			"capture/user_id=b/ts=10/raw/1.json",
+		];
+
+		let idx = DatapathIndex::new(paths.into_iter());
+
+		// `10` and `10.0` are different partitions, and `10.0` is newer
+		let latest = idx
+			.latest("capture/**", &["user_id"], "ts", KeyOrder::Numeric)
+			.unwrap();
+		assert_eq!(latest[0].value, "10.0");
+		assert_eq!(
+			latest[0].paths,
+			vec!["capture/user_id=a/ts=10.0/raw/1.json"]
+		);
+
+		// `1a` isn't a number, so it is skipped
+		assert_eq!(latest[1].value, "10");
+
+		// Groups with no numbers are skipped
+		let idx = DatapathIndex::new(["capture/user_id=a/ts=x/raw/1.json"].into_iter());
+		let latest = idx
+			.latest("capture/**", &["user_id"], "ts", KeyOrder::Numeric)
+			.unwrap();
+		assert!(latest.is_empty());
+	}
+
+	#[test]
+	fn latest_datapath() {
+		let idx = index();
+
+		let latest = idx
+			.latest_datapath::<CaptureRaw>(
+				(Wildcardable::Star, Wildcardable::Star),
+				&["user_id"],
+				"ts",
+				KeyOrder::Numeric,
+			)
+			.unwrap();
+
+		assert_eq!(latest.len(), 2);
+		assert_eq!(latest[0].group, vec!["a"]);
+		assert_eq!(latest[0].paths.len(), 2);
+		assert_eq!(latest[0].paths[0].path.ts, 10);
+		assert_eq!(latest[0].paths[1].file, "2.json");
+
+		// `ts=x` isn't a number
+		assert_eq!(latest[1].value, "3");
+
+		let latest = idx
+			.latest_datapath::<CaptureRaw>(
+				(Wildcardable::Value("b".to_owned()), Wildcardable::Value(3)),
+				&["user_id"],
+				"ts",
+				KeyOrder::Numeric,
+			)
+			.unwrap();
+		assert_eq!(latest.len(), 1);
+		assert_eq!(latest[0].paths[0].path.user_id, "b");
+
+		// Values can make invalid queries
+		let latest = idx.latest_datapath::<CaptureRaw>(
+			(Wildcardable::Value("***".to_owned()), Wildcardable::Star),
+			&["user_id"],
+			"ts",
+			KeyOrder::Numeric,
+		);
+		assert!(latest.is_none());
+	}
+}
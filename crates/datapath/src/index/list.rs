@@ -0,0 +1,116 @@
+use crate::DatapathIndex;
+
+/// A "directory" returned by [DatapathIndex::list_dir]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CommonPrefix {
+	/// This directory's prefix, ending with `/`
+	pub prefix: String,
+
+	/// The number of paths below this prefix
+	pub count: usize,
+}
+
+impl DatapathIndex {
+	/// List this index like `aws s3 ls`, with a `/` delimiter.
+	///
+	/// Returns the common prefixes ("directories") and the objects
+	/// directly under `prefix`, both in order. Like S3, `prefix` doesn't
+	/// need to end at a `/`: `web/domain=` lists every `web/domain=*/`.
+	///
+	/// This only visits the children of the node `prefix` ends in.
+	pub fn list_dir(&self, prefix: &str) -> (Vec<CommonPrefix>, Vec<String>) {
+		let (dir, partial) = match prefix.rsplit_once('/') {
+			Some((dir, partial)) => (Some(dir), partial),
+			None => (None, prefix),
+		};
+
+		let node = match dir {
+			None => Some(&self.root),
+			Some(dir) => self.root.get(&dir.split('/').collect::<Vec<_>>()),
+		};
+
+		let mut prefixes = Vec::new();
+		let mut objects = Vec::new();
+		let Some(node) = node else {
+			return (prefixes, objects);
+		};
+
+		let children = node
+			.children
+			.iter_from(partial)
+			.take_while(|(seg, _)| seg.starts_with(partial));
+
+		for (seg, child) in children {
+			let path = match dir {
+				None => seg.to_owned(),
+				Some(dir) => format!("{dir}/{seg}"),
+			};
+
+			let below = child.count() - usize::from(child.object().is_some());
+			if below != 0 {
+				prefixes.push(CommonPrefix {
+					prefix: format!("{path}/"),
+					count: below,
+				});
+			}
+
+			if child.object().is_some() {
+				objects.push(path);
+			}
+		}
+
+		return (prefixes, objects);
+	}
+}
+
+#[cfg(test)]
+mod list_tests {
+	use super::*;
+
+	fn dirs(prefixes: &[CommonPrefix]) -> Vec<(&str, usize)> {
+		prefixes
+			.iter()
+			.map(|x| (x.prefix.as_str(), x.count))
+			.collect()
+	}
+
+	#[test]
+	fn list_dir() {
+		let paths = vec![
+			"web/domain=a.com/ts=1/data.json",
+			"web/domain=a.com/ts=2/data.json",
+			"web/domain=b.com/ts=1/data.json",
+			"web/readme.txt",
+			"web",
+			"api/data.json",
+		];
+		let idx = DatapathIndex::new(paths.into_iter());
+
+		let (prefixes, objects) = idx.list_dir("");
+		assert_eq!(dirs(&prefixes), vec![("api/", 1), ("web/", 4)]);
+		assert_eq!(objects, vec!["web"]);
+
+		let (prefixes, objects) = idx.list_dir("web/");
+		assert_eq!(
+			dirs(&prefixes),
+			vec![("web/domain=a.com/", 2), ("web/domain=b.com/", 1)]
+		);
+		assert_eq!(objects, vec!["web/readme.txt"]);
+
+		// Prefixes don't need to end at a delimiter
+		let (prefixes, objects) = idx.list_dir("web/domain=a");
+		assert_eq!(dirs(&prefixes), vec![("web/domain=a.com/", 2)]);
+		assert!(objects.is_empty());
+
+		let (prefixes, objects) = idx.list_dir("we");
+		assert_eq!(dirs(&prefixes), vec![("web/", 4)]);
+		assert_eq!(objects, vec!["web"]);
+
+		let (prefixes, objects) = idx.list_dir("web/domain=a.com/ts=1/");
+		assert!(prefixes.is_empty());
+		assert_eq!(objects, vec!["web/domain=a.com/ts=1/data.json"]);
+
+		let (prefixes, objects) = idx.list_dir("missing/");
+		assert!(prefixes.is_empty() && objects.is_empty());
+	}
+}
@@ -0,0 +1,304 @@
+use std::{collections::HashSet, sync::Arc};
+use tracing::trace;
+
+use crate::{
+	Datapath, DatapathFile, DatapathIndex, Rule,
+	index::query::{Matcher, Matches},
+};
+
+/// The default name of the files that mark complete partitions
+pub(crate) const DEFAULT_MARKER: &str = "_SUCCESS";
+
+impl DatapathIndex {
+	/// Set the name of the files that writers add to a partition once
+	/// it is complete. The default is `_SUCCESS`.
+	///
+	/// Like hidden files, markers are never matched by wildcards.
+	/// The old marker is no longer special: `with_marker("DONE")` still
+	/// hides `_SUCCESS`, but only because it starts with `_`.
+	pub fn with_marker(mut self, name: impl Into<String>) -> Self {
+		self.marker = Arc::from(name.into());
+		self
+	}
+
+	/// Choose whether wildcards match hidden files.
+	///
+	/// Following Hive, files and directories that start with `_` or `.`
+	/// are hidden by default: `web/**` doesn't match `web/_SUCCESS` or
+	/// `web/.tmp/data.json`. They are still matched by name, so the query
+	/// `web/_SUCCESS` finds `web/_SUCCESS`, as does `web/_*`.
+	pub fn with_hidden_files(mut self, show: bool) -> Self {
+		self.show_hidden = show;
+		self
+	}
+
+	/// Find the partitions of the paths that match `query` that have a
+	/// marker file (see [Self::with_marker]), in order.
+	///
+	/// A path's partition is the path without its last segment.
+	/// Markers and hidden files count here even though wildcards don't
+	/// match them, so a partition with only a marker is complete and
+	/// one with only hidden files is incomplete. Partitions in hidden
+	/// directories (like `web/_temporary`) are skipped, unless `query`
+	/// names them or hidden files are shown (see [Self::with_hidden_files]).
+	///
+	/// Returns `None` if the query was invalid.
+	pub fn complete_partitions(&self, query: impl Into<String>) -> Option<Vec<String>> {
+		let rule = Rule::new(query)?;
+		Some(self.complete_partitions_rule(&rule))
+	}
+
+	/// Like [Self::complete_partitions], but with a precompiled rule
+	pub fn complete_partitions_rule(&self, rule: &Rule) -> Vec<String> {
+		self.partitions(rule, true)
+	}
+
+	/// Like [Self::complete_partitions], but find
+	/// the partitions that don't have a marker file
+	pub fn incomplete_partitions(&self, query: impl Into<String>) -> Option<Vec<String>> {
+		let rule = Rule::new(query)?;
+		Some(self.incomplete_partitions_rule(&rule))
+	}
+
+	/// Like [Self::incomplete_partitions], but with a precompiled rule
+	pub fn incomplete_partitions_rule(&self, rule: &Rule) -> Vec<String> {
+		self.partitions(rule, false)
+	}
+
+	/// Find the partitions of the paths that match `rule`
+	/// that are (or aren't) complete, in the order we first see them
+	fn partitions(&self, rule: &Rule, complete: bool) -> Vec<String> {
+		trace!("DatapathIndex query is {}", rule.pattern());
+
+		// Markers and hidden files are partition contents too,
+		// so we look at every path. `visible` checks the partitions.
+		let visible = self.matcher(rule);
+		let mut seen = HashSet::new();
+		let mut out = Vec::new();
+		let mut matches = Matches::new(&self.root, Matcher::new(rule));
+		while let Some((path, _)) = matches.next_ref() {
+			let Some((_, partition)) = path.segments().split_last() else {
+				continue;
+			};
+
+			let joined = partition.join("/");
+			if seen.contains(&joined) {
+				continue;
+			}
+
+			let states = partition
+				.iter()
+				.fold(visible.start(), |states, seg| visible.step(states, seg));
+
+			if !states.is_empty() && self.is_complete(partition) == complete {
+				out.push(joined.clone());
+			}
+			seen.insert(joined);
+		}
+
+		return out;
+	}
+
+	/// Returns `true` if the partition at `segments` has a marker file
+	fn is_complete(&self, segments: &[&str]) -> bool {
+		self.root
+			.get(segments)
+			.and_then(|x| x.children.get(&self.marker))
+			.is_some_and(|x| x.object().is_some())
+	}
+
+	/// Get every path that matches `path` (see [Datapath::from_wildcardable])
+	/// and parses as `D`, in order.
+	///
+	/// Returns `None` if a value in `path` makes an invalid query, like `***`.
+	pub fn query_datapath<D: Datapath>(
+		&self,
+		path: D::WildcardableTuple,
+	) -> Option<Vec<DatapathFile<D>>> {
+		let rule = Rule::datapath::<D>(path)?;
+
+		Some(
+			self.query_rule(&rule)
+				.filter_map(|x| D::parse(&x))
+				.collect(),
+		)
+	}
+
+	/// Like [Self::query_datapath], but only returns files in
+	/// complete datapaths: those with a marker file (see [Self::with_marker]).
+	///
+	/// Use this to skip partitions that are still being written.
+	///
+	/// Returns `None` if a value in `path` makes an invalid query, like `***`.
+	pub fn query_complete_datapath<D: Datapath>(
+		&self,
+		path: D::WildcardableTuple,
+	) -> Option<Vec<DatapathFile<D>>> {
+		let rule = Rule::datapath::<D>(path)?;
+		// The datapath is every segment but the trailing `**`
+		let depth = rule.pattern().split('/').count() - 1;
+		trace!("DatapathIndex query is {}", rule.pattern());
+
+		// Files in the same datapath are adjacent,
+		// so we only need to remember the last one.
+		let mut last: (Vec<String>, bool) = (Vec::new(), false);
+		let mut files = Vec::new();
+		let mut matches = Matches::new(&self.root, self.matcher(&rule));
+		while let Some((file, _)) = matches.next_ref() {
+			let segments = file.segments();
+			let partition = &segments[..depth.min(segments.len())];
+
+			if !last
+				.0
+				.iter()
+				.map(String::as_str)
+				.eq(partition.iter().copied())
+			{
+				let complete = self.is_complete(partition);
+				last = (
+					partition.iter().map(|x| (*x).to_owned()).collect(),
+					complete,
+				);
+			}
+
+			if last.1
+				&& let Some(x) = D::parse(&file.to_string())
+			{
+				files.push(x);
+			}
+		}
+
+		return Some(files);
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod marker_tests {
+	use super::*;
+	use crate::{Wildcardable, index::test_paths::CaptureRaw};
+
+	fn paths() -> Vec<&'static str> {
+		vec![
+			"web/ts=1/a.json",
+			"web/ts=1/_SUCCESS",
+			"web/ts=2/a.json",
+			"web/ts=2/.a.json.crc",
+			"web/ts=3/_temporary/0/a.json",
+			"web/ts=3/b.json",
+			"web/ts=4/DONE",
+		]
+	}
+
+	#[test]
+	fn hidden_files() {
+		let idx = DatapathIndex::new(paths().into_iter());
+		let all = idx.query("web/**").unwrap().collect::<Vec<_>>();
+		assert_eq!(
+			all,
+			vec![
+				"web/ts=1/a.json",
+				"web/ts=2/a.json",
+				"web/ts=3/b.json",
+				"web/ts=4/DONE"
+			]
+		);
+		assert_eq!(idx.query_count("web/**"), Some(4));
+		assert_eq!(idx.query_count("**/*.json"), Some(3));
+
+		// Hidden files are still matched by name
+		assert_eq!(idx.query_count("web/*/_SUCCESS"), Some(1));
+		assert_eq!(idx.query_count("web/*/.*"), Some(1));
+		assert_eq!(idx.query_count("web/ts=3/_temporary/**"), Some(1));
+		assert_eq!(idx.query_count("**/_*/**"), Some(2));
+
+		let idx = idx.with_hidden_files(true);
+		assert_eq!(idx.query_count("web/**"), Some(7));
+		assert_eq!(idx.query("web/**").unwrap().count(), 7);
+
+		// Custom markers are hidden too
+		let idx = DatapathIndex::new(paths().into_iter()).with_marker("DONE");
+		assert_eq!(idx.query_count("web/**"), Some(3));
+		assert_eq!(idx.query("web/**").unwrap().count(), 3);
+		assert_eq!(idx.query_count("web/*/DONE"), Some(1));
+
+		// Markers that no path has don't hide anything
+		let idx = DatapathIndex::new(paths().into_iter()).with_marker("READY");
+		assert_eq!(idx.query_count("web/**"), Some(4));
+		assert_eq!(idx.query("web/**").unwrap().count(), 4);
+	}
+
+	#[test]
+	fn partitions() {
+		let mut paths = paths();
+		paths.extend(["web/ts=5/_SUCCESS", "web/ts=6/.a.json.crc"]);
+		let idx = DatapathIndex::new(paths.into_iter());
+		// Partitions with only a marker are complete, and partitions
+		// with only hidden files are incomplete. `_temporary` is hidden.
+		assert_eq!(
+			idx.complete_partitions("web/**").unwrap(),
+			vec!["web/ts=1", "web/ts=5"]
+		);
+		assert_eq!(
+			idx.incomplete_partitions("web/**").unwrap(),
+			vec!["web/ts=2", "web/ts=3", "web/ts=4", "web/ts=6"]
+		);
+		assert_eq!(
+			idx.incomplete_partitions("web/ts=3/_temporary/**").unwrap(),
+			vec!["web/ts=3/_temporary/0"]
+		);
+
+		let idx = idx.with_marker("DONE");
+		assert_eq!(idx.complete_partitions("web/**").unwrap(), vec!["web/ts=4"]);
+		assert_eq!(
+			idx.incomplete_partitions("web/**").unwrap(),
+			vec!["web/ts=1", "web/ts=2", "web/ts=3", "web/ts=5", "web/ts=6"]
+		);
+
+		let idx = idx.with_hidden_files(true);
+		assert_eq!(
+			idx.incomplete_partitions("web/ts=3/**").unwrap(),
+			vec!["web/ts=3/_temporary/0", "web/ts=3"]
+		);
+
+		assert!(idx.complete_partitions("web/***").is_none());
+	}
+
+	#[test]
+	fn complete_datapaths() {
+		let paths = vec![
+			"capture/user_id=a/ts=1/raw/1.json",
+			"capture/user_id=a/ts=1/raw/_SUCCESS",
+			"capture/user_id=a/ts=2/raw/1.json",
+			"capture/user_id=a/ts=2/raw/x/_SUCCESS",
+			"capture/user_id=b/ts=1/raw/x/1.json",
+			"capture/user_id=b/ts=1/raw/_SUCCESS",
+		];
+		let idx = DatapathIndex::new(paths.into_iter());
+		let all = (Wildcardable::Star, Wildcardable::Star);
+
+		assert_eq!(
+			idx.query_datapath::<CaptureRaw>(all.clone()).unwrap().len(),
+			3
+		);
+
+		let files = idx
+			.query_complete_datapath::<CaptureRaw>(all)
+			.unwrap()
+			.into_iter()
+			.map(|x| x.to_string())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			files,
+			vec![
+				"capture/user_id=a/ts=1/raw/1.json",
+				"capture/user_id=b/ts=1/raw/x/1.json"
+			]
+		);
+
+		// Values can make invalid queries
+		let bad = (Wildcardable::Value("***".to_owned()), Wildcardable::Star);
+		assert!(idx.query_datapath::<CaptureRaw>(bad.clone()).is_none());
+		assert!(idx.query_complete_datapath::<CaptureRaw>(bad).is_none());
+	}
+}
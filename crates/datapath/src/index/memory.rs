@@ -0,0 +1,77 @@
+use std::mem::size_of;
+
+use crate::DatapathIndex;
+
+/// An estimate of the memory used by a [DatapathIndex], in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+	/// The number of nodes in this index's trie
+	pub nodes: usize,
+
+	/// The number of distinct path segments
+	pub segments: usize,
+
+	/// Memory used by trie nodes and their child tables
+	pub trie_bytes: usize,
+
+	/// Memory used by distinct path segments
+	pub segment_bytes: usize,
+
+	/// Memory used by object metadata
+	pub meta_bytes: usize,
+
+	/// The memory it would take to store each path as its own `String`,
+	/// for comparison with [Self::total]
+	pub uncompressed_bytes: usize,
+}
+
+impl MemoryUsage {
+	/// The total memory used by this index
+	pub fn total(&self) -> usize {
+		size_of::<DatapathIndex>() + self.trie_bytes + self.segment_bytes + self.meta_bytes
+	}
+}
+
+impl DatapathIndex {
+	/// Estimate the memory used by this index.
+	/// This walks the whole index, so it isn't free.
+	pub fn memory_usage(&self) -> MemoryUsage {
+		let mut usage = MemoryUsage::default();
+		self.root.memory_usage(None, &mut usage);
+		self.segments.memory_usage(&mut usage);
+		return usage;
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod memory_tests {
+	use super::*;
+
+	#[test]
+	fn shared_segments() {
+		let paths = (0..1000)
+			.flat_map(|d| (0..3).map(move |t| format!("web/domain=d{d}.com/ts={t}/data.json")));
+		let idx = DatapathIndex::new(paths);
+		let usage = idx.memory_usage();
+
+		// `web`, 1000 domains, 3 timestamps, and `data.json`
+		assert_eq!(usage.segments, 1 + 1000 + 3 + 1);
+		assert_eq!(usage.nodes, 1 + 1 + 1000 + 3000 + 3000);
+		assert_eq!(
+			usage.uncompressed_bytes,
+			idx.query("**")
+				.unwrap()
+				.map(|x| x.len() + size_of::<String>())
+				.sum::<usize>()
+		);
+		assert_eq!(usage.meta_bytes, 0);
+
+		// Removing paths frees segments nothing else uses
+		let mut idx = idx;
+		for t in 0..3 {
+			idx.remove(&format!("web/domain=d0.com/ts={t}/data.json"));
+		}
+		assert_eq!(idx.memory_usage().segments, 1 + 999 + 3 + 1);
+	}
+}
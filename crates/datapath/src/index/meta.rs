@@ -0,0 +1,24 @@
+use std::time::SystemTime;
+
+/// Metadata about one object in a [crate::DatapathIndex].
+///
+/// Every field is optional, since not every listing source
+/// provides all of them (an index built from plain strings has none).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ObjectMeta {
+	/// The size of this object, in bytes
+	pub size: Option<u64>,
+
+	/// The time this object was last modified
+	pub last_modified: Option<SystemTime>,
+
+	/// This object's ETag, without surrounding quotes
+	pub etag: Option<String>,
+}
+
+impl ObjectMeta {
+	/// Returns `true` if no metadata is known
+	pub fn is_empty(&self) -> bool {
+		self.size.is_none() && self.last_modified.is_none() && self.etag.is_none()
+	}
+}
@@ -1,174 +1,186 @@
-use itertools::Itertools;
-use std::{collections::HashMap, fmt::Display, str::FromStr};
+use std::{
+	borrow::{Borrow, Cow},
+	collections::BTreeMap,
+	ops::RangeBounds,
+	sync::Arc,
+};
 use tracing::trace;
-use trie_rs::map::{Trie, TrieBuilder};
+
+mod compaction;
+pub use compaction::{CompactionBatch, CompactionPolicy};
+
+mod diff;
+pub use diff::IndexDiff;
+
+mod explain;
+pub use explain::QueryPlan;
+
+mod latest;
+pub use latest::{KeyOrder, Latest};
+
+mod list;
+pub use list::CommonPrefix;
+
+mod marker;
+use marker::DEFAULT_MARKER;
+
+mod memory;
+pub use memory::MemoryUsage;
+
+mod meta;
+pub use meta::ObjectMeta;
+
+mod overlay;
+pub use overlay::{IndexSet, SourcedPath};
+
+mod page;
+pub use page::{QueryOptions, QueryOrder, QueryPage};
+
+mod query;
+use query::{Matcher, Matches, StateSet};
+pub use query::{PathRef, QueryCursor};
+
+#[cfg(feature = "rayon")]
+mod parallel;
+
+mod range;
+use range::ValueRange;
+
+mod registry;
+pub use registry::{Classification, DatapathRegistry};
+
+#[cfg(feature = "chrono")]
+mod retention;
+#[cfg(feature = "chrono")]
+pub use retention::{ExpiredPartition, RetentionPolicy, TimeFormat};
 
 mod rule;
 pub use rule::Rule;
 
-/// A path segment in an [`AnyDatapath`]
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
-enum PathSegment {
-	/// A constant value, like `web`
-	Constant(String),
+mod sample;
 
-	/// A key=value partition, like `domain=gouletpens.com`
-	Value { key: String, value: String },
-}
+mod shared;
+pub use shared::{IndexUpdate, SharedIndex};
 
-impl Display for PathSegment {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		match self {
-			PathSegment::Constant(x) => write!(f, "{x}"),
-			PathSegment::Value { key, value } => write!(f, "{key}={value}"),
-		}
-	}
-}
+#[cfg(feature = "tokio")]
+mod source;
+#[cfg(all(feature = "tokio", feature = "s3"))]
+pub use source::ListObjectsV2Source;
+#[cfg(feature = "tokio")]
+pub use source::{DirectorySource, ListingPage, ListingSource, VecSource};
 
-impl FromStr for PathSegment {
-	type Err = ();
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		if s.contains("\n") {
-			return Err(());
-		}
+mod stats;
+pub use stats::{FileNames, KeyStats};
 
-		if s.is_empty() {
-			return Err(());
-		}
+#[cfg(feature = "tokio")]
+mod stream;
 
-		return Ok(if s.contains("=") {
-			let mut s = s.split("=");
-			let key = s.next().ok_or(())?.to_owned();
-			let value = s.join("=");
-			Self::Value { key, value }
-		} else {
-			Self::Constant(s.to_owned())
-		});
-	}
-}
+#[cfg(test)]
+mod test_paths;
+
+mod trie;
+use trie::{Interner, Node};
+
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "s3")]
+pub use s3::{
+	InventoryFile, InventoryManifest, ListObjectsV2Page, S3Event, S3EventApplier, S3EventKind,
+	S3ParseError, read_inventory_csv, read_s3_events,
+};
 
 //
 // MARK: index
 //
 
 /// An in-memory cache of s3 paths.
-#[derive(Debug)]
+///
+/// Paths are stored in a trie of `/`-separated segments, so queries
+/// only visit the parts of the index that their pattern can match:
+/// literal segments (like `web` or `domain=example.com`) are looked up
+/// directly, and wildcards only fan out over the children they need.
+///
+/// Each distinct segment is stored once and shared by every path that
+/// has it, see [Self::memory_usage].
+///
+/// An index is a set of paths. A path that is added more than once
+/// is stored, counted by [Self::len], and returned by queries only once.
+/// Query results are sorted segment-by-segment, not returned in the order
+/// their paths were added.
+///
+/// An index holds at most `u32::MAX` (about 4 billion) paths.
+/// Adding more panics.
+#[derive(Debug, Clone)]
 pub struct DatapathIndex {
-	patterns: Trie<u8, Vec<String>>,
-	len: usize,
-}
+	root: Node,
 
-impl DatapathIndex {
-	/// Convert a query string to a trie search key by normalizing values to `*`.
-	/// Stops at the first wildcard constant since it can't be used for prefix matching.
-	fn query_to_key(query: &str) -> String {
-		let trimmed = query.trim().trim_end_matches("**").trim_matches('/');
-		let mut segments = Vec::new();
-		for seg in trimmed.split('/') {
-			let segment = match PathSegment::from_str(&seg) {
-				Ok(x) => x,
-				Err(_) => continue,
-			};
-
-			// lone stars and double-stars aren't in the trie
-			if matches!(segment, PathSegment::Constant(ref s) if s == "*" || s == "**" ) {
-				break;
-			}
+	/// The distinct segments in `root`, shared by all edges
+	segments: Interner,
 
-			segments.push(segment);
-		}
+	/// How file names appear in [Self::patterns]
+	file_names: FileNames,
 
-		segments.iter_mut().for_each(|x| match x {
-			PathSegment::Constant(_) => {}
-			PathSegment::Value { value, .. } => *value = "*".into(),
-		});
+	/// The name of the files that mark complete partitions
+	marker: Arc<str>,
 
-		segments.iter().join("/")
-	}
+	/// If `true`, wildcards match hidden files
+	show_hidden: bool,
+}
 
+impl DatapathIndex {
 	pub fn new_empty() -> Self {
 		Self {
-			patterns: TrieBuilder::new().build(),
-			len: 0,
+			root: Node::default(),
+			segments: Interner::default(),
+			file_names: FileNames::default(),
+			marker: Arc::from(DEFAULT_MARKER),
+			show_hidden: false,
 		}
 	}
 
+	/// Create an index of the given paths.
+	/// Duplicate paths are only stored once.
 	pub fn new<S: Into<String>, I: Iterator<Item = S>>(paths: I) -> Self {
-		let mut len = 0;
-		let mut patterns = HashMap::new();
-
-		for s in paths {
-			let s: String = s.into();
-			let mut segments = Vec::new();
-			for seg in s.split('/') {
-				segments.push(match PathSegment::from_str(&seg) {
-					Ok(x) => x,
-					Err(_) => continue,
-				});
-			}
-
-			segments.iter_mut().for_each(|x| match x {
-				PathSegment::Constant(_) => {}
-				PathSegment::Value { value, .. } => *value = "*".into(),
-			});
-
-			let pattern = segments.iter().join("/");
-
-			patterns.entry(pattern).or_insert(Vec::new()).push(s);
-			len += 1;
-		}
-
-		let mut builder = TrieBuilder::new();
-		for (k, v) in patterns {
-			builder.push(k, v);
-		}
+		Self::new_with_meta(paths.map(|s| (s, ObjectMeta::default())))
+	}
 
-		Self {
-			len,
-			patterns: builder.build(),
+	/// Like [Self::new], but attaches [ObjectMeta] to each path.
+	/// If a path is given more than once, its last metadata is kept.
+	pub fn new_with_meta<S: Into<String>, I: Iterator<Item = (S, ObjectMeta)>>(objects: I) -> Self {
+		let mut index = Self::new_empty();
+		for (s, meta) in objects {
+			index.insert(&s.into(), meta);
 		}
+		index
 	}
 
+	/// Like [Self::from_stream], but reads paths from a channel
 	#[cfg(feature = "tokio")]
 	pub async fn async_new<S: Into<String>>(mut paths: tokio::sync::mpsc::Receiver<S>) -> Self {
-		let mut len = 0;
-		let mut patterns = HashMap::new();
-
-		while let Some(s) = paths.recv().await {
-			let s: String = s.into();
-			let mut segments = Vec::new();
-			for seg in s.split('/') {
-				segments.push(match PathSegment::from_str(&seg) {
-					Ok(x) => x,
-					Err(_) => continue,
-				});
-			}
-
-			segments.iter_mut().for_each(|x| match x {
-				PathSegment::Constant(_) => {}
-				PathSegment::Value { value, .. } => *value = "*".into(),
-			});
-
-			let pattern = segments.iter().join("/");
-
-			patterns.entry(pattern).or_insert(Vec::new()).push(s);
-			len += 1;
-		}
+		Self::from_stream(futures::stream::poll_fn(|cx| paths.poll_recv(cx))).await
+	}
 
-		let mut builder = TrieBuilder::new();
-		for (k, v) in patterns {
-			builder.push(k, v);
-		}
+	/// Add a path to this index, replacing its metadata if it already exists.
+	/// Returns `true` if this path is new.
+	///
+	/// Panics if this index already holds `u32::MAX` paths.
+	pub fn insert(&mut self, path: &str, meta: ObjectMeta) -> bool {
+		let segments = path.split('/').collect::<Vec<_>>();
+		self.root.insert(&segments, meta, &mut self.segments)
+	}
 
-		Self {
-			len,
-			patterns: builder.build(),
-		}
+	/// Remove a path from this index.
+	/// Returns `true` if this path existed.
+	pub fn remove(&mut self, path: &str) -> bool {
+		let segments = path.split('/').collect::<Vec<_>>();
+		self.root.remove(&segments, &mut self.segments)
 	}
 
+	/// The number of distinct paths in this index.
+	/// Paths that were added more than once are counted once.
 	#[inline(always)]
 	pub fn len(&self) -> usize {
-		self.len
+		self.root.count()
 	}
 
 	#[inline(always)]
@@ -176,70 +188,304 @@ impl DatapathIndex {
 		self.len() == 0
 	}
 
+	/// Make a matcher for `rule` that follows this index's settings
+	fn matcher<R: Borrow<rule::Rule>>(&self, rule: R) -> Matcher<R> {
+		let matcher = Matcher::new(rule);
+		if self.show_hidden {
+			return matcher;
+		}
+
+		// If no path has a segment named like the marker, we don't need to hide it.
+		// This keeps counts fast, see [Matcher::accepts_subtree].
+		let marker = self.segments.contains(&self.marker).then_some(&self.marker);
+		return matcher.hiding(marker);
+	}
+
+	/// Get the metadata of the path `path`.
+	///
+	/// Returns `None` if this path is not in the index.
+	pub fn get_meta(&self, path: &str) -> Option<&ObjectMeta> {
+		let segments = path.split('/').collect::<Vec<_>>();
+		self.root.get(&segments)?.object()
+	}
+
 	/// Given a datapath (that may contain wildcards) as a query,
 	/// return all known datapaths that match it.
+	/// Paths are returned in order, sorted segment-by-segment.
+	///
+	/// Wildcards may appear anywhere in the query. A `*` segment only
+	/// visits the children of the nodes it is reached at, and matching
+	/// continues exactly after it. A `**` visits every node below the
+	/// point it is reached at.
 	///
 	/// Returns an empty iterator if no paths match.
 	/// Returns `None` if the query was invalid.
 	pub fn query(&self, query: impl Into<String>) -> Option<impl Iterator<Item = String> + '_> {
-		let query: String = query.into();
-		let regex = rule::Rule::new(query.clone())?;
-		let key = Self::query_to_key(&query);
-		trace!("DatapathIndex key is {key}");
+		Some(self.query_with_meta(query)?.map(|(path, _)| path))
+	}
 
-		Some(
-			self.patterns
-				.predictive_search::<String, _>(&key)
-				.flat_map(|(_, strings)| strings.iter())
-				.filter(move |s| regex.is_match(s))
-				.cloned(),
-		)
+	/// Like [Self::query], but also returns each path's [ObjectMeta]
+	pub fn query_with_meta(
+		&self,
+		query: impl Into<String>,
+	) -> Option<impl Iterator<Item = (String, &ObjectMeta)> + '_> {
+		let rule = rule::Rule::new(query)?;
+		trace!("DatapathIndex query is {}", rule.pattern());
+		Some(Matches::new(&self.root, self.matcher(rule)))
 	}
 
 	/// Like [Self::query], but with a precompiled rule
 	pub fn query_rule<'a>(&'a self, rule: &'a rule::Rule) -> impl Iterator<Item = String> + 'a {
-		let key = Self::query_to_key(rule.pattern());
-		trace!("DatapathIndex key is {key}");
+		trace!("DatapathIndex query is {}", rule.pattern());
+		Matches::new(&self.root, self.matcher(rule)).map(|(path, _)| path)
+	}
 
-		self.patterns
-			.predictive_search::<String, _>(&key)
-			.flat_map(|(_, strings)| strings.iter())
-			.filter(move |s| rule.is_match(s))
-			.cloned()
+	/// Like [Self::query], but borrows each path from this index
+	/// instead of allocating a `String` for it.
+	///
+	/// Returns `None` if the query was invalid.
+	pub fn query_cursor(&self, query: impl Into<String>) -> Option<QueryCursor<'_>> {
+		let rule = rule::Rule::new(query)?;
+		trace!("DatapathIndex query is {}", rule.pattern());
+		Some(QueryCursor::new(&self.root, self.matcher(Cow::Owned(rule))))
 	}
 
-	/// Like [Self::query], but returns `true` if any paths match
-	pub fn query_match(&self, query: impl Into<String>) -> Option<bool> {
-		let query: String = query.into();
-		let regex = rule::Rule::new(query.clone())?;
-		let key = Self::query_to_key(&query);
-		trace!("DatapathIndex key is {key}");
-
-		for (_, strings) in self.patterns.predictive_search::<String, _>(&key) {
-			for s in strings {
-				if regex.is_match(s) {
-					return Some(true);
-				}
+	/// Like [Self::query_cursor], but with a precompiled rule
+	pub fn query_rule_cursor<'a>(&'a self, rule: &'a rule::Rule) -> QueryCursor<'a> {
+		trace!("DatapathIndex query is {}", rule.pattern());
+		QueryCursor::new(&self.root, self.matcher(Cow::Borrowed(rule)))
+	}
+
+	/// Call `f` with every path that matches `query`, in order,
+	/// without allocating a `String` for each one.
+	///
+	/// Returns `None` if the query was invalid.
+	pub fn query_for_each(
+		&self,
+		query: impl Into<String>,
+		f: impl FnMut(PathRef<'_>, &ObjectMeta),
+	) -> Option<()> {
+		let rule = rule::Rule::new(query)?;
+		self.query_rule_for_each(&rule, f);
+		Some(())
+	}
+
+	/// Like [Self::query_for_each], but with a precompiled rule
+	pub fn query_rule_for_each(
+		&self,
+		rule: &rule::Rule,
+		mut f: impl FnMut(PathRef<'_>, &ObjectMeta),
+	) {
+		let mut cursor = self.query_rule_cursor(rule);
+		while let Some((path, meta)) = cursor.next_path() {
+			f(path, meta);
+		}
+	}
+
+	/// Count the paths that match `query`.
+	///
+	/// This doesn't visit paths below a point where everything
+	/// matches (like `web/**`), so it is much faster than counting
+	/// the results of [Self::query].
+	///
+	/// Returns `None` if the query was invalid.
+	pub fn query_count(&self, query: impl Into<String>) -> Option<usize> {
+		let rule = rule::Rule::new(query)?;
+		Some(self.query_rule_count(&rule))
+	}
+
+	/// Like [Self::query_count], but with a precompiled rule
+	pub fn query_rule_count(&self, rule: &rule::Rule) -> usize {
+		trace!("DatapathIndex query is {}", rule.pattern());
+		let matcher = self.matcher(rule);
+		Self::count_matches(&matcher, &self.root, matcher.start(), &mut Vec::new())
+	}
+
+	/// Count all paths below `node` that match.
+	///
+	/// `path` is the path to `node`. We only track it if
+	/// [Matcher::verify] needs it, so counting doesn't build paths.
+	fn count_matches<'a>(
+		matcher: &Matcher<&rule::Rule>,
+		node: &'a Node,
+		states: StateSet,
+		path: &mut Vec<&'a str>,
+	) -> usize {
+		let mut count = 0;
+		let mut children = matcher.children(node, states);
+		while let Some((seg, child)) = matcher.next_child(&mut children) {
+			let next = matcher.step(states, seg);
+			if next.is_empty() {
+				continue;
+			}
+
+			// Every path below here matches, so we don't need to visit them
+			if matcher.accepts_subtree(child, next) {
+				count += child.count();
+				continue;
+			}
+
+			let track = matcher.is_fallback();
+			if track {
+				path.push(seg);
+			}
+
+			if child.object().is_some() && matcher.accepts(next) && matcher.verify(path) {
+				count += 1;
+			}
+
+			if matcher.can_continue(next) {
+				count += Self::count_matches(matcher, child, next, path);
+			}
+
+			if track {
+				path.pop();
 			}
 		}
 
-		return Some(false);
+		return count;
+	}
+
+	/// Like [Self::query], but returns `true` if any paths match
+	pub fn query_match(&self, query: impl Into<String>) -> Option<bool> {
+		let rule = rule::Rule::new(query)?;
+		Some(self.query_rule_match(&rule))
 	}
 
 	/// Like [Self::query_match], but with a precompiled rule
-	pub fn query_rule_match<'a>(&'a self, rule: &'a rule::Rule) -> bool {
-		let key = Self::query_to_key(&rule.pattern());
-		trace!("DatapathIndex key is {key}");
-
-		for (_, strings) in self.patterns.predictive_search::<String, _>(&key) {
-			for s in strings {
-				if rule.is_match(s) {
-					return true;
-				}
+	pub fn query_rule_match(&self, rule: &rule::Rule) -> bool {
+		self.query_rule_cursor(rule).next_path().is_some()
+	}
+
+	/// Given a datapath (that may contain wildcards) as a query,
+	/// return all known datapaths that match it and have a value of `key`
+	/// inside `range`, like every `ts` in `1700000000..1700086400`.
+	///
+	/// Values that parse as numbers are compared numerically, and come
+	/// before all other values, which are compared as strings. So `10.0`
+	/// is in `..=10`, and `1a` is in `10..`. Paths without `key` are never returned,
+	/// and paths that have `key` more than once need every value in `range`.
+	///
+	/// Returns `None` if the query was invalid.
+	pub fn query_range<V: ToString>(
+		&self,
+		query: impl Into<String>,
+		key: &str,
+		range: impl RangeBounds<V>,
+	) -> Option<impl Iterator<Item = String> + '_> {
+		let rule = rule::Rule::new(query)?;
+		trace!("DatapathIndex query is {}", rule.pattern());
+		Some(
+			Matches::new(&self.root, self.matcher(rule))
+				.with_range(ValueRange::new(key, range))
+				.map(|(path, _)| path),
+		)
+	}
+
+	/// Like [Self::query_range], but with a precompiled rule
+	pub fn query_range_rule<'a, V: ToString>(
+		&'a self,
+		rule: &'a rule::Rule,
+		key: &str,
+		range: impl RangeBounds<V>,
+	) -> impl Iterator<Item = String> + 'a {
+		trace!("DatapathIndex query is {}", rule.pattern());
+		Matches::new(&self.root, self.matcher(rule))
+			.with_range(ValueRange::new(key, range))
+			.map(|(path, _)| path)
+	}
+
+	/// Given a query, return each distinct value of the partition `key`
+	/// among all matching paths, with the number of paths that have it.
+	/// Values are returned in lexicographic order.
+	///
+	/// Paths without `key` are ignored. If `key` appears more than once
+	/// in a path, only its first value is used.
+	/// Returns `None` if the query was invalid.
+	pub fn distinct_values(
+		&self,
+		query: impl Into<String>,
+		key: &str,
+	) -> Option<impl Iterator<Item = (String, usize)>> {
+		let rule = rule::Rule::new(query)?;
+		Some(self.distinct_counts(&rule, key).into_iter())
+	}
+
+	/// Like [Self::distinct_values], but with a precompiled rule
+	pub fn distinct_values_rule(
+		&self,
+		rule: &rule::Rule,
+		key: &str,
+	) -> impl Iterator<Item = (String, usize)> {
+		self.distinct_counts(rule, key).into_iter()
+	}
+
+	fn distinct_counts(&self, rule: &rule::Rule, key: &str) -> BTreeMap<String, usize> {
+		trace!("DatapathIndex query is {}", rule.pattern());
+
+		let matcher = self.matcher(rule);
+		let mut counts = BTreeMap::new();
+		Self::count_values(
+			&matcher,
+			&self.root,
+			matcher.start(),
+			key,
+			None,
+			&mut Vec::new(),
+			&mut counts,
+		);
+
+		counts.into_iter().map(|(k, v)| (k.to_owned(), v)).collect()
+	}
+
+	/// Count the values of `key` in all paths below `node` that match.
+	/// `value` is the value of `key` we found above `node`, if any.
+	fn count_values<'a>(
+		matcher: &Matcher<&rule::Rule>,
+		node: &'a Node,
+		states: StateSet,
+		key: &str,
+		value: Option<&'a str>,
+		path: &mut Vec<&'a str>,
+		counts: &mut BTreeMap<&'a str, usize>,
+	) {
+		let mut children = matcher.children(node, states);
+		while let Some((seg, child)) = matcher.next_child(&mut children) {
+			let next = matcher.step(states, seg);
+			if next.is_empty() {
+				continue;
+			}
+
+			let value = value.or_else(|| {
+				seg.split_once('=')
+					.filter(|(k, _)| *k == key)
+					.map(|(_, v)| v)
+			});
+
+			// Every path below here matches, so we don't need to visit them
+			if let Some(value) = value
+				&& matcher.accepts_subtree(child, next)
+			{
+				*counts.entry(value).or_default() += child.count();
+				continue;
+			}
+
+			path.push(seg);
+
+			if let Some(value) = value
+				&& child.object().is_some()
+				&& matcher.accepts(next)
+				&& matcher.verify(path)
+			{
+				*counts.entry(value).or_default() += 1;
 			}
-		}
 
-		return false;
+			if matcher.can_continue(next) {
+				Self::count_values(matcher, child, next, key, value, path, counts);
+			}
+
+			path.pop();
+		}
 	}
 }
 
@@ -381,6 +627,7 @@ mod index_tests {
 	}
 
 	#[test]
+	#[expect(clippy::bool_assert_comparison)]
 	fn query_match() {
 		let paths = vec![
 			"web/domain=example.com/ts=1234",
@@ -425,4 +672,356 @@ mod index_tests {
 			.collect();
 		assert_eq!(results.len(), 2);
 	}
+
+	#[test]
+	fn distinct_values() {
+		let paths = vec![
+			"capture/user_id=a/ts=1/raw/file.json",
+			"capture/user_id=a/ts=2/raw/file.json",
+			"capture/user_id=b/ts=1/raw/file.json",
+			"capture/user_id=b/ts=1/raw/other.json",
+			"capture/name/file.json",
+			"web/user_id=c/ts=1/file.json",
+		];
+		let idx = DatapathIndex::new(paths.into_iter());
+
+		let users: Vec<_> = idx
+			.distinct_values("capture/**", "user_id")
+			.unwrap()
+			.collect();
+		assert_eq!(users, vec![("a".to_owned(), 2), ("b".to_owned(), 2)]);
+
+		let ts: Vec<_> = idx
+			.distinct_values("capture/user_id=b/**", "ts")
+			.unwrap()
+			.collect();
+		assert_eq!(ts, vec![("1".to_owned(), 2)]);
+
+		let users: Vec<_> = idx
+			.distinct_values("**/ts=1/**", "user_id")
+			.unwrap()
+			.collect();
+		assert_eq!(
+			users,
+			vec![
+				("a".to_owned(), 1),
+				("b".to_owned(), 2),
+				("c".to_owned(), 1)
+			]
+		);
+
+		assert_eq!(
+			idx.distinct_values("capture/**", "missing")
+				.unwrap()
+				.count(),
+			0
+		);
+	}
+
+	#[test]
+	fn duplicate_paths() {
+		let paths = vec![
+			"web/domain=example.com",
+			"web/domain=other.com",
+			"web/domain=example.com",
+		];
+		let mut idx = DatapathIndex::new(paths.into_iter());
+
+		// Duplicates are counted and returned once
+		assert_eq!(idx.len(), 2);
+		assert_eq!(
+			idx.query("web/**").unwrap().collect::<Vec<_>>(),
+			vec!["web/domain=example.com", "web/domain=other.com"]
+		);
+
+		assert!(!idx.insert("web/domain=other.com", ObjectMeta::default()));
+		assert_eq!(idx.len(), 2);
+
+		// One remove deletes every copy
+		assert!(idx.remove("web/domain=example.com"));
+		assert_eq!(idx.len(), 1);
+		assert_eq!(idx.query("web/domain=example.com").unwrap().count(), 0);
+	}
+
+	#[test]
+	fn sorted_results() {
+		let paths = vec!["b/x=2", "a/x=3", "b/x=1", "a/x=1"];
+		let idx = DatapathIndex::new(paths.into_iter());
+
+		// Results are sorted, not in insertion order
+		let results: Vec<_> = idx.query("*/x=*").unwrap().collect();
+		assert_eq!(results, vec!["a/x=1", "a/x=3", "b/x=1", "b/x=2"]);
+	}
+
+	/// The trie walk must return exactly what the rule's regex matches
+	#[test]
+	fn matches_rule() {
+		let paths = vec![
+			"",
+			"web",
+			"web/",
+			"web//file.json",
+			"web/domain=example.com",
+			"web/domain=example.com/ts=1234",
+			"web/domain=example.com/ts=1234/file.json",
+			"web/domain=example.com/ts=1234/a/b/c.json",
+			"web/domain=other.com/ts=1234/file.json",
+			"web/domain=other.com/ts=5678/file.txt",
+			"api/domain=example.com/ts=1234/file.json",
+			"api/web/web/web",
+			"x/y/x/y/x",
+			"root/test",
+			"root/a/test",
+			"root/testfile",
+			"root/test/file",
+			"root/.flac",
+			"/leading",
+		];
+
+		// Rules don't hide files, so neither should the index
+		let idx = DatapathIndex::new(paths.iter().copied()).with_hidden_files(true);
+
+		let queries = [
+			"",
+			"*",
+			"**",
+			"**/*",
+			"web",
+			"web/**",
+			"web/*",
+			"web/*/*",
+			"*/domain=example.com/ts=1234/*",
+			"*/domain=*/ts=1234/**",
+			"web/domain=*.com/**/*.json",
+			"**/ts=1234/**",
+			"**/ts=*",
+			"**/file.json",
+			"**/web/**",
+			"**/x/**/x",
+			"x/**/y/**",
+			"root/**test",
+			"root/test**",
+			"root/test**file",
+			"**.flac",
+			"**/*.flac",
+			"web//file.json",
+			"/leading",
+			"*/*/*/*",
+			"**/*a*",
+		];
+
+		for q in queries {
+			let rule = Rule::new(q).unwrap();
+			let mut expected: Vec<_> = paths.iter().copied().filter(|p| rule.is_match(p)).collect();
+			expected.sort();
+
+			let mut results: Vec<_> = idx.query(q).unwrap().collect();
+			results.sort();
+
+			assert_eq!(results, expected, "query `{q}`");
+			assert_eq!(idx.query_match(q).unwrap(), !expected.is_empty());
+		}
+	}
+
+	/// Very long patterns fall back to regex matching
+	#[test]
+	fn long_query() {
+		let long = (0..100)
+			.map(|i| i.to_string())
+			.collect::<Vec<_>>()
+			.join("/");
+		let idx = DatapathIndex::new([long.clone(), "0/1".into()].into_iter());
+
+		let results: Vec<_> = idx.query(long.clone()).unwrap().collect();
+		assert_eq!(results, vec![long.clone()]);
+
+		let pattern = long.replace("/50/", "/*/");
+		let results: Vec<_> = idx.query(pattern).unwrap().collect();
+		assert_eq!(results, vec![long]);
+	}
+
+	#[test]
+	fn query_range() {
+		let paths = vec![
+			"web/domain=a.com/ts=99/data.json",
+			"web/domain=a.com/ts=100/data.json",
+			"web/domain=a.com/ts=150/data.json",
+			"web/domain=b.com/ts=199.5/data.json",
+			"web/domain=b.com/ts=200/data.json",
+			"web/domain=b.com/other.json",
+			"api/domain=a.com/ts=150/data.json",
+			"web/date=2024-01-31/data.json",
+			"web/date=2024-02-01/data.json",
+		];
+		let idx = DatapathIndex::new(paths.into_iter());
+
+		// Numeric, not lexical, order
+		let results: Vec<_> = idx
+			.query_range("web/domain=*/ts=*/data.json", "ts", 100..200)
+			.unwrap()
+			.collect();
+		assert_eq!(
+			results,
+			vec![
+				"web/domain=a.com/ts=100/data.json",
+				"web/domain=a.com/ts=150/data.json",
+				"web/domain=b.com/ts=199.5/data.json",
+			]
+		);
+
+		// Combined with the glob, and paths without `ts` are skipped
+		let results: Vec<_> = idx
+			.query_range("**/domain=a.com/**", "ts", 150..)
+			.unwrap()
+			.collect();
+		assert_eq!(
+			results,
+			vec![
+				"api/domain=a.com/ts=150/data.json",
+				"web/domain=a.com/ts=150/data.json",
+			]
+		);
+
+		// Lexical order for values that aren't numbers
+		let results: Vec<_> = idx
+			.query_range("web/**", "date", "2024-02".."2024-03")
+			.unwrap()
+			.collect();
+		assert_eq!(results, vec!["web/date=2024-02-01/data.json"]);
+
+		let rule = Rule::new("**").unwrap();
+		assert_eq!(idx.query_range_rule(&rule, "ts", ..=100).count(), 2);
+		assert_eq!(idx.query_range_rule(&rule, "missing", 0..).count(), 0);
+		assert!(idx.query_range("web/***", "ts", 0..1).is_none());
+	}
+
+	#[test]
+	fn insert_and_remove() {
+		let mut idx = DatapathIndex::new(["web/ts=1/a", "web/ts=1/b", "web/ts=2/a"].into_iter());
+
+		assert!(!idx.insert("web/ts=1/a", ObjectMeta::default()));
+		assert!(idx.insert("web/ts=3/a", ObjectMeta::default()));
+		assert_eq!(idx.len(), 4);
+
+		assert!(idx.remove("web/ts=1/a"));
+		assert!(!idx.remove("web/ts=1/a"));
+		assert!(!idx.remove("web/ts=1"));
+		assert!(!idx.remove("missing/path"));
+		assert_eq!(idx.len(), 3);
+
+		assert!(idx.remove("web/ts=1/b"));
+		assert_eq!(
+			idx.distinct_values("web/**", "ts")
+				.unwrap()
+				.collect::<Vec<_>>(),
+			vec![("2".to_owned(), 1), ("3".to_owned(), 1)]
+		);
+
+		// Empty branches are dropped
+		assert!(
+			idx.root
+				.children
+				.get("web")
+				.unwrap()
+				.children
+				.get("ts=1")
+				.is_none()
+		);
+
+		assert!(idx.remove("web/ts=2/a"));
+		assert!(idx.remove("web/ts=3/a"));
+		assert!(idx.is_empty());
+		assert!(idx.root.children.is_empty());
+	}
+
+	#[test]
+	fn borrowed_results() {
+		let paths = vec![
+			"web/domain=a.com/ts=1/data.json",
+			"web/domain=a.com/ts=2/data.json",
+			"web/domain=b.com/ts=1/data.json",
+			"api/domain=a.com/ts=1/data.json",
+			"web/x",
+			"",
+		];
+		let idx = DatapathIndex::new(paths.into_iter());
+
+		for query in [
+			"**",
+			"web/**",
+			"*/domain=a.com/**",
+			"web/*/ts=1/*",
+			"",
+			"none/**",
+		] {
+			let owned = idx.query(query).unwrap().collect::<Vec<_>>();
+
+			let mut cursor = idx.query_cursor(query).unwrap();
+			let mut borrowed = Vec::new();
+			while let Some((path, _)) = cursor.next_path() {
+				assert_eq!(path, owned[borrowed.len()].as_str());
+				assert_eq!(path.len(), owned[borrowed.len()].len());
+				borrowed.push(path.to_string());
+			}
+			assert_eq!(borrowed, owned);
+
+			let mut each = Vec::new();
+			idx.query_for_each(query, |path, _| each.push(String::from(path)))
+				.unwrap();
+			assert_eq!(each, owned);
+
+			assert_eq!(idx.query_count(query).unwrap(), owned.len(), "{query}");
+		}
+
+		let mut cursor = idx.query_cursor("web/x").unwrap();
+		let (path, _) = cursor.next_path().unwrap();
+		assert_eq!(path.segments(), &["web", "x"]);
+		assert!(path != "web/y");
+		assert!(path != "web/x/");
+		assert!(!path.is_empty());
+
+		let mut cursor = idx.query_cursor("").unwrap();
+		assert!(cursor.next_path().unwrap().0.is_empty());
+
+		// Long patterns fall back to regex matching
+		let long = ["*"; 70].join("/");
+		assert_eq!(idx.query_count(&long).unwrap(), 0);
+		assert!(idx.query_count("web/***").is_none());
+
+		// And still see the paths they count
+		let deep = DatapathIndex::new([["a"; 70].join("/"), ["b"; 71].join("/")].into_iter());
+		assert!(
+			deep.matcher(&Rule::new(long.clone()).unwrap())
+				.is_fallback()
+		);
+		assert_eq!(deep.query_count(&long).unwrap(), 1);
+		assert_eq!(deep.query_count(format!("b/{long}")).unwrap(), 1);
+	}
+
+	/// Wildcards in the middle of a query shouldn't visit the whole index
+	#[test]
+	fn mid_path_wildcards_prune() {
+		let paths = (0..100).flat_map(|d| {
+			["web", "api"].map(move |s| format!("{s}/domain=d{d}.com/ts={}/file.json", d % 3))
+		});
+		let idx = DatapathIndex::new(paths);
+		assert_eq!(idx.len(), 200);
+
+		let visited = |q: &str| {
+			let rule = Rule::new(q).unwrap();
+			let mut matches = Matches::new(&idx.root, Matcher::new(&rule));
+			let count = matches.by_ref().count();
+			(count, matches.visited())
+		};
+
+		// Both roots, then one domain, ts, and file under each
+		assert_eq!(visited("*/domain=d5.com/ts=2/file.json"), (2, 8));
+		assert_eq!(visited("web/*/ts=0/file.json"), (34, 1 + 100 + 34 * 2));
+		assert_eq!(visited("*/domain=d5.com/*/*"), (2, 8));
+		assert_eq!(visited("web/domain=d5.com/**"), (1, 4));
+
+		// These require a full scan
+		assert_eq!(visited("**/ts=2/file.json"), (66, 602));
+		assert_eq!(visited("**"), (200, 602));
+	}
 }
@@ -0,0 +1,425 @@
+use std::{borrow::Borrow, cmp::Ordering, collections::HashSet, sync::Arc};
+use tracing::trace;
+
+use crate::{
+	Datapath, DatapathFile, DatapathIndex, Rule,
+	index::query::{Matcher, Matches},
+};
+
+/// A path returned by an [IndexSet], with the source of the index it is in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourcedPath<'a, P = String> {
+	/// The label of the index this path is in, like a bucket name
+	pub source: &'a str,
+	pub path: P,
+}
+
+/// One index in an [IndexSet]
+#[derive(Debug, Clone)]
+struct Layer {
+	source: String,
+	index: DatapathIndex,
+
+	/// Paths deleted from this layer, which hide the same paths in lower layers
+	deleted: HashSet<String>,
+}
+
+/// Compare two paths segment-by-segment, like the order of query results
+fn compare_paths(a: &str, b: &str) -> Ordering {
+	a.split('/').cmp(b.split('/'))
+}
+
+//
+// MARK: set
+//
+
+/// A stack of [DatapathIndex]es that are queried as one.
+///
+/// Each index is labeled with a source, like the bucket it indexes,
+/// that is returned with each result. Indices that are pushed later are
+/// higher: a path deleted from a higher index (see [Self::delete]) is
+/// hidden in all lower indices.
+///
+/// Results are in order, like the results of [DatapathIndex::query].
+/// A path in more than one index is returned from the highest index first.
+#[derive(Debug, Clone, Default)]
+pub struct IndexSet {
+	/// Lowest first
+	layers: Vec<Layer>,
+
+	/// If `true`, each path is only returned once
+	dedup: bool,
+}
+
+impl IndexSet {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// If `true`, paths in more than one index are only returned once,
+	/// from the highest index that has them. This is `false` by default.
+	pub fn with_dedup(mut self, dedup: bool) -> Self {
+		self.dedup = dedup;
+		self
+	}
+
+	/// Add an index labeled `source` above all others.
+	/// If an index labeled `source` already exists,
+	/// it is replaced and keeps its place.
+	///
+	/// `index` is newer than every delete from this index so far,
+	/// so deletes of the paths it has from this index are forgotten.
+	/// Deletes from higher indices still hide them.
+	pub fn push(&mut self, source: impl Into<String>, index: DatapathIndex) -> &mut Self {
+		let source = source.into();
+		let i = match self.layers.iter().position(|x| x.source == source) {
+			Some(i) => {
+				self.layers[i].index = index;
+				i
+			}
+			None => {
+				self.layers.push(Layer {
+					source,
+					index,
+					deleted: HashSet::new(),
+				});
+				self.layers.len() - 1
+			}
+		};
+
+		let Layer { index, deleted, .. } = &mut self.layers[i];
+		deleted.retain(|x| index.get_meta(x).is_none());
+
+		return self;
+	}
+
+	/// Get the index labeled `source`
+	pub fn get(&self, source: &str) -> Option<&DatapathIndex> {
+		self.layers
+			.iter()
+			.find(|x| x.source == source)
+			.map(|x| &x.index)
+	}
+
+	/// Get the index labeled `source`.
+	/// Unlike [Self::push], deletes from this index are kept,
+	/// so paths inserted here are still hidden in lower indices.
+	pub fn get_mut(&mut self, source: &str) -> Option<&mut DatapathIndex> {
+		self.layers
+			.iter_mut()
+			.find(|x| x.source == source)
+			.map(|x| &mut x.index)
+	}
+
+	/// Get the labels of all indices, lowest first
+	pub fn sources(&self) -> impl Iterator<Item = &str> {
+		self.layers.iter().map(|x| x.source.as_str())
+	}
+
+	/// Remove `path` from the index labeled `source`,
+	/// and hide it in all lower indices.
+	/// Returns `false` if there is no index labeled `source`.
+	pub fn delete(&mut self, source: &str, path: &str) -> bool {
+		let Some(layer) = self.layers.iter_mut().find(|x| x.source == source) else {
+			return false;
+		};
+
+		layer.index.remove(path);
+		layer.deleted.insert(path.to_owned());
+		return true;
+	}
+
+	/// Count the visible paths in all indices.
+	///
+	/// Like [DatapathIndex::len], this counts hidden files.
+	/// Deleted paths aren't counted, and if dedup is enabled
+	/// (see [Self::with_dedup]) each path is only counted once.
+	/// If either is the case, this walks every index.
+	pub fn len(&self) -> usize {
+		if !self.dedup && self.layers.iter().all(|x| x.deleted.is_empty()) {
+			return self.layers.iter().map(|x| x.index.len()).sum();
+		}
+
+		#[expect(clippy::unwrap_used)]
+		let rule = Rule::new("**").unwrap();
+		let streams = self
+			.layers
+			.iter()
+			.map(|x| Matches::new(&x.index.root, Matcher::new(&rule)).map(|(path, _)| path))
+			.collect();
+
+		self.merge(streams).count()
+	}
+
+	#[inline(always)]
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Like [DatapathIndex::query], but queries every index in this set.
+	/// Each index follows its own settings, like [DatapathIndex::with_hidden_files].
+	///
+	/// Returns `None` if the query was invalid.
+	pub fn query(
+		&self,
+		query: impl Into<String>,
+	) -> Option<impl Iterator<Item = SourcedPath<'_>> + '_> {
+		let rule = Rule::new(query)?;
+		trace!("IndexSet query is {}", rule.pattern());
+		Some(self.query_layers(Arc::new(rule)))
+	}
+
+	/// Like [Self::query], but with a precompiled rule
+	pub fn query_rule<'a>(&'a self, rule: &'a Rule) -> impl Iterator<Item = SourcedPath<'a>> + 'a {
+		trace!("IndexSet query is {}", rule.pattern());
+		self.query_layers(rule)
+	}
+
+	/// Query every layer with `rule`, and merge the results
+	fn query_layers<'a, R: Borrow<Rule> + Clone + 'a>(
+		&'a self,
+		rule: R,
+	) -> impl Iterator<Item = SourcedPath<'a>> + 'a {
+		let streams = self
+			.layers
+			.iter()
+			.map(|x| {
+				Matches::new(&x.index.root, x.index.matcher(rule.clone())).map(|(path, _)| path)
+			})
+			.collect();
+
+		self.merge(streams)
+	}
+
+	/// Like [Self::query], but returns `true` if any paths match
+	pub fn query_match(&self, query: impl Into<String>) -> Option<bool> {
+		let rule = Rule::new(query)?;
+		Some(self.query_rule_match(&rule))
+	}
+
+	/// Like [Self::query_match], but with a precompiled rule
+	pub fn query_rule_match(&self, rule: &Rule) -> bool {
+		self.query_rule(rule).next().is_some()
+	}
+
+	/// Like [DatapathIndex::query_datapath], but queries every index in this set.
+	///
+	/// Returns `None` if a value in `path` makes an invalid query, like `***`.
+	pub fn query_datapath<D: Datapath>(
+		&self,
+		path: D::WildcardableTuple,
+	) -> Option<Vec<SourcedPath<'_, DatapathFile<D>>>> {
+		let rule = Rule::datapath::<D>(path)?;
+
+		trace!("IndexSet query is {}", rule.pattern());
+		Some(
+			self.query_layers(Arc::new(rule))
+				.filter_map(|x| {
+					Some(SourcedPath {
+						source: x.source,
+						path: D::parse(&x.path)?,
+					})
+				})
+				.collect(),
+		)
+	}
+
+	/// Returns `true` if `path` was deleted from a layer above `layer`
+	fn is_shadowed(&self, layer: usize, path: &str) -> bool {
+		self.layers
+			.iter()
+			.skip(layer + 1)
+			.any(|x| x.deleted.contains(path))
+	}
+
+	/// Merge the results of each layer into one ordered iterator.
+	/// `streams` has one ordered iterator for each layer, lowest first.
+	fn merge<'a, I: Iterator<Item = String> + 'a>(
+		&'a self,
+		mut streams: Vec<I>,
+	) -> impl Iterator<Item = SourcedPath<'a>> + 'a {
+		let mut heads = streams.iter_mut().map(|x| x.next()).collect::<Vec<_>>();
+		let mut last: Option<String> = None;
+
+		std::iter::from_fn(move || {
+			loop {
+				// Find the smallest next path. Ties go to the higher layer.
+				let mut best: Option<(usize, &String)> = None;
+				for (i, head) in heads.iter().enumerate() {
+					if let Some(path) = head
+						&& best.is_none_or(|(_, x)| compare_paths(path, x) != Ordering::Greater)
+					{
+						best = Some((i, path));
+					}
+				}
+
+				let i = best?.0;
+				let (head, stream, layer) =
+					(heads.get_mut(i)?, streams.get_mut(i)?, self.layers.get(i)?);
+				let path = std::mem::replace(head, stream.next())?;
+
+				if self.is_shadowed(i, &path) {
+					continue;
+				}
+
+				if self.dedup {
+					if last.as_ref() == Some(&path) {
+						continue;
+					}
+					last = Some(path.clone());
+				}
+
+				return Some(SourcedPath {
+					source: &layer.source,
+					path,
+				});
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod overlay_tests {
+	use super::*;
+	use crate::{Wildcardable, index::test_paths::CaptureRaw};
+
+	fn set() -> IndexSet {
+		let a = DatapathIndex::new(
+			[
+				"capture/user_id=a/ts=1/raw/1.json",
+				"capture/user_id=a/ts=2/raw/1.json",
+				"web/domain=a.com/index.html",
+			]
+			.into_iter(),
+		);
+		let b = DatapathIndex::new(
+			[
+				"capture/user_id=a/ts=2/raw/1.json",
+				"capture/user_id=b/ts=1/raw/1.json",
+			]
+			.into_iter(),
+		);
+		let recent = DatapathIndex::new(["capture/user_id=a/ts=3/raw/1.json"].into_iter());
+
+		let mut set = IndexSet::new();
+		set.push("bucket-a", a)
+			.push("bucket-b", b)
+			.push("recent", recent);
+		set
+	}
+
+	fn results(set: &IndexSet, query: &str) -> Vec<(String, String)> {
+		set.query(query)
+			.unwrap()
+			.map(|x| (x.source.to_owned(), x.path))
+			.collect()
+	}
+
+	#[test]
+	fn query() {
+		let set = set();
+		assert_eq!(
+			set.sources().collect::<Vec<_>>(),
+			vec!["bucket-a", "bucket-b", "recent"]
+		);
+
+		let r = results(&set, "capture/**");
+		let expected = [
+			("bucket-a", "capture/user_id=a/ts=1/raw/1.json"),
+			("bucket-b", "capture/user_id=a/ts=2/raw/1.json"),
+			("bucket-a", "capture/user_id=a/ts=2/raw/1.json"),
+			("recent", "capture/user_id=a/ts=3/raw/1.json"),
+			("bucket-b", "capture/user_id=b/ts=1/raw/1.json"),
+		]
+		.map(|(s, p)| (s.to_owned(), p.to_owned()));
+		assert_eq!(r, expected);
+
+		assert_eq!(set.len(), 6);
+		assert!(set.query_match("web/*/*").unwrap());
+		assert!(!set.query_match("api/**").unwrap());
+		assert!(set.query("capture/***").is_none());
+
+		let set = set.with_dedup(true);
+		let r = results(&set, "capture/user_id=a/ts=2/**");
+		assert_eq!(r.len(), 1);
+		assert_eq!(r[0].0, "bucket-b");
+		assert_eq!(set.len(), 5);
+	}
+
+	#[test]
+	fn delete() {
+		let mut set = set();
+		assert!(set.delete("bucket-b", "capture/user_id=a/ts=1/raw/1.json"));
+		assert!(set.delete("recent", "capture/user_id=b/ts=1/raw/1.json"));
+		assert!(!set.delete("missing", "web/domain=a.com/index.html"));
+
+		let r = results(&set, "capture/**");
+		let expected = [
+			("bucket-b", "capture/user_id=a/ts=2/raw/1.json"),
+			("bucket-a", "capture/user_id=a/ts=2/raw/1.json"),
+			("recent", "capture/user_id=a/ts=3/raw/1.json"),
+		]
+		.map(|(s, p)| (s.to_owned(), p.to_owned()));
+		assert_eq!(r, expected);
+		assert_eq!(set.len(), 4);
+
+		// Deletes don't hide paths in higher indices
+		set.get_mut("recent")
+			.unwrap()
+			.insert("capture/user_id=b/ts=1/raw/1.json", Default::default());
+		assert_eq!(set.query("capture/user_id=b/**").unwrap().count(), 1);
+
+		// Nor in the index they were deleted from
+		set.get_mut("bucket-b")
+			.unwrap()
+			.insert("capture/user_id=a/ts=1/raw/1.json", Default::default());
+		let r = results(&set, "capture/user_id=a/ts=1/**");
+		assert_eq!(r.len(), 1);
+		assert_eq!(r[0].0, "bucket-b");
+	}
+
+	#[test]
+	fn push_after_delete() {
+		let mut set = set();
+		let path = "capture/user_id=a/ts=2/raw/1.json";
+		set.delete("bucket-b", path);
+		assert_eq!(set.query(path).unwrap().count(), 0);
+
+		// Replacing an index shows the paths it has again
+		set.push("bucket-b", DatapathIndex::new([path].into_iter()));
+		let r = results(&set, path);
+		assert_eq!(r.len(), 2);
+		assert_eq!(r[0].0, "bucket-b");
+		assert_eq!(r[1].0, "bucket-a");
+
+		// But deletes from higher indices still hide them
+		set.delete("recent", path);
+		set.push("bucket-b", DatapathIndex::new([path].into_iter()));
+		assert_eq!(set.query(path).unwrap().count(), 0);
+
+		// Deletes of other paths are kept
+		set.delete("recent", "web/domain=a.com/index.html");
+		set.push("bucket-b", DatapathIndex::new_empty());
+		assert_eq!(set.query("**").unwrap().count(), 2);
+	}
+
+	#[test]
+	fn query_datapath() {
+		let set = set();
+		let files = set
+			.query_datapath::<CaptureRaw>((Wildcardable::Value("a".to_owned()), Wildcardable::Star))
+			.unwrap();
+		assert_eq!(files.len(), 4);
+		assert_eq!(files[0].source, "bucket-a");
+		assert_eq!(files[3].source, "recent");
+		assert_eq!(files[3].path.path.ts, 3);
+
+		// Values can make invalid queries
+		let files = set.query_datapath::<CaptureRaw>((
+			Wildcardable::Value("***".to_owned()),
+			Wildcardable::Star,
+		));
+		assert!(files.is_none());
+	}
+}
@@ -0,0 +1,424 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+use tracing::trace;
+
+use crate::{
+	DatapathIndex, Rule,
+	index::{query::Matches, range::compare_values},
+};
+
+/// The order of the results of [DatapathIndex::query_page]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum QueryOrder {
+	/// Sorted segment-by-segment, like [DatapathIndex::query].
+	/// Pages in this order skip straight to their continuation token.
+	///
+	/// This isn't the byte order that S3 lists keys in (and that its `start-after`
+	/// uses): segment-by-segment, `a/b` comes before `a-b`, since `a` is a prefix
+	/// of `a-b`. In S3, `a-b` comes first, since `-` is before `/`.
+	#[default]
+	Lexicographic,
+
+	/// Sorted by the value of this partition key, then segment-by-segment.
+	/// Values that are numbers come first and are compared numerically,
+	/// then all other values are compared as strings (see [DatapathIndex::query_range]).
+	/// Paths without this key come last.
+	///
+	/// Matches aren't stored in this order, so every page in this order visits
+	/// every match. Each page of `n` paths takes `O(matches * log n)` time,
+	/// so prefer large pages.
+	ByKey(String),
+}
+
+/// Options for [DatapathIndex::query_page]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryOptions {
+	/// The order to return paths in
+	pub order: QueryOrder,
+
+	/// The maximum number of paths to return.
+	/// If `None`, return all of them. Must not be zero.
+	pub limit: Option<usize>,
+
+	/// Resume after the last page, using its [QueryPage::next_token]
+	pub continuation_token: Option<String>,
+}
+
+impl QueryOptions {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn with_order(mut self, order: QueryOrder) -> Self {
+		self.order = order;
+		self
+	}
+
+	/// Return at most `limit` paths on each page.
+	/// Pages with a limit of zero are invalid.
+	pub fn with_limit(mut self, limit: usize) -> Self {
+		self.limit = Some(limit);
+		self
+	}
+
+	pub fn with_continuation_token(mut self, token: impl Into<String>) -> Self {
+		self.continuation_token = Some(token.into());
+		self
+	}
+}
+
+/// One page of results from [DatapathIndex::query_page]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryPage {
+	pub paths: Vec<String>,
+
+	/// Pass this to [QueryOptions::with_continuation_token] to get the next page.
+	/// `None` if this is the last page.
+	pub next_token: Option<String>,
+}
+
+//
+// MARK: tokens
+//
+
+/// Make a continuation token that resumes after `path`.
+///
+/// Tokens are hex, so that they are safe to put in urls,
+/// and record their order so that they can't be mixed up.
+fn encode_token(order: &QueryOrder, path: &str) -> String {
+	let raw = match order {
+		QueryOrder::Lexicographic => format!("l\0{path}"),
+		QueryOrder::ByKey(key) => format!("k\0{key}\0{path}"),
+	};
+
+	raw.bytes().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Get the path a continuation token resumes after.
+/// Returns `None` if this token is invalid or for another order.
+fn decode_token(order: &QueryOrder, token: &str) -> Option<String> {
+	if !token.len().is_multiple_of(2) {
+		return None;
+	}
+
+	let bytes = (0..token.len())
+		.step_by(2)
+		.map(|i| u8::from_str_radix(token.get(i..i + 2)?, 16).ok())
+		.collect::<Option<Vec<_>>>()?;
+	let raw = String::from_utf8(bytes).ok()?;
+
+	match order {
+		QueryOrder::Lexicographic => raw.strip_prefix("l\0").map(|x| x.to_owned()),
+		QueryOrder::ByKey(key) => raw
+			.strip_prefix("k\0")?
+			.strip_prefix(key.as_str())?
+			.strip_prefix('\0')
+			.map(|x| x.to_owned()),
+	}
+}
+
+//
+// MARK: pages
+//
+
+/// The value of `key` in `path`, if it has one
+fn value_of<'a>(path: &'a str, key: &str) -> Option<&'a str> {
+	path.split('/').find_map(|seg| {
+		seg.split_once('=')
+			.filter(|(k, _)| *k == key)
+			.map(|(_, v)| v)
+	})
+}
+
+/// Compare paths by the value of `key`, then segment-by-segment.
+/// This is a total order, since [compare_values] is.
+fn compare_by_key(key: &str, a: &str, b: &str) -> Ordering {
+	let by_value = match (value_of(a, key), value_of(b, key)) {
+		(Some(a), Some(b)) => compare_values(a, b),
+		(Some(_), None) => Ordering::Less,
+		(None, Some(_)) => Ordering::Greater,
+		(None, None) => Ordering::Equal,
+	};
+
+	by_value.then_with(|| a.split('/').cmp(b.split('/')))
+}
+
+/// A path, ordered by [compare_by_key]
+struct ByKey<'a> {
+	key: &'a str,
+	path: String,
+}
+
+impl PartialEq for ByKey<'_> {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other).is_eq()
+	}
+}
+
+impl Eq for ByKey<'_> {}
+
+impl PartialOrd for ByKey<'_> {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for ByKey<'_> {
+	fn cmp(&self, other: &Self) -> Ordering {
+		compare_by_key(self.key, &self.path, &other.path)
+	}
+}
+
+impl DatapathIndex {
+	/// Like [Self::query], but returns one page of results at a time.
+	///
+	/// Returns `None` if the query, limit, or continuation token was invalid.
+	pub fn query_page(
+		&self,
+		query: impl Into<String>,
+		options: &QueryOptions,
+	) -> Option<QueryPage> {
+		let rule = Rule::new(query)?;
+		self.query_rule_page(&rule, options)
+	}
+
+	/// Like [Self::query_page], but with a precompiled rule
+	pub fn query_rule_page(&self, rule: &Rule, options: &QueryOptions) -> Option<QueryPage> {
+		trace!("DatapathIndex query is {}", rule.pattern());
+
+		let after = match &options.continuation_token {
+			None => None,
+			Some(token) => Some(decode_token(&options.order, token)?),
+		};
+		let limit = options.limit.unwrap_or(usize::MAX);
+		if limit == 0 {
+			// We couldn't make progress, or tell the caller where to resume
+			return None;
+		}
+
+		let (paths, more) = match &options.order {
+			QueryOrder::Lexicographic => {
+				let mut matches = match &after {
+					None => Matches::new(&self.root, self.matcher(rule)),
+					Some(after) => {
+						let after = after.split('/').collect::<Vec<_>>();
+						Matches::new_after(&self.root, self.matcher(rule), &after)
+					}
+				};
+
+				let paths = matches
+					.by_ref()
+					.take(limit)
+					.map(|(path, _)| path)
+					.collect::<Vec<_>>();
+				(paths, matches.next_ref().is_some())
+			}
+
+			QueryOrder::ByKey(key) => {
+				// Keep the first `limit + 1` paths, so we know if there are more
+				let keep = limit.saturating_add(1);
+				let mut first = BinaryHeap::new();
+				for (path, _) in Matches::new(&self.root, self.matcher(rule)) {
+					if after
+						.as_ref()
+						.is_some_and(|after| compare_by_key(key, &path, after).is_le())
+					{
+						continue;
+					}
+
+					first.push(ByKey { key, path });
+					if first.len() > keep {
+						first.pop();
+					}
+				}
+
+				let mut paths = first
+					.into_sorted_vec()
+					.into_iter()
+					.map(|x| x.path)
+					.collect::<Vec<_>>();
+				let more = paths.len() > limit;
+				paths.truncate(limit);
+				(paths, more)
+			}
+		};
+
+		let next_token = paths
+			.last()
+			.filter(|_| more)
+			.map(|last| encode_token(&options.order, last));
+
+		Some(QueryPage { paths, next_token })
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod page_tests {
+	use super::*;
+	use crate::index::query::Matcher;
+
+	fn all_pages(idx: &DatapathIndex, query: &str, options: QueryOptions) -> Vec<Vec<String>> {
+		let mut pages = Vec::new();
+		let mut options = options;
+		loop {
+			let page = idx.query_page(query, &options).unwrap();
+			pages.push(page.paths);
+			match page.next_token {
+				None => return pages,
+				Some(token) => options = options.with_continuation_token(token),
+			}
+		}
+	}
+
+	#[test]
+	fn lexicographic_pages() {
+		let paths = vec![
+			"web/ts=1/a",
+			"web/ts=1/b",
+			"web/ts=10/a",
+			"web/ts=2/a",
+			"web/ts=2",
+			"web/x-y/a",
+			"api/ts=1/a",
+			"web",
+		];
+		let idx = DatapathIndex::new(paths.into_iter());
+
+		for query in ["**", "web/**", "web/ts=*/*", "*/ts=1/*", "web/ts=1*/**"] {
+			let all = idx.query(query).unwrap().collect::<Vec<_>>();
+			for limit in 1..=all.len() + 1 {
+				let pages = all_pages(&idx, query, QueryOptions::new().with_limit(limit));
+				assert!(pages.iter().all(|x| x.len() <= limit));
+				assert_eq!(pages.concat(), all, "{query} {limit}");
+			}
+		}
+
+		// Resuming after a path that isn't in the index
+		let after = encode_token(&QueryOrder::Lexicographic, "web/ts=10/b");
+		let page = idx
+			.query_page(
+				"web/**",
+				&QueryOptions::new().with_continuation_token(after),
+			)
+			.unwrap();
+		assert_eq!(page.paths, vec!["web/ts=2", "web/ts=2/a", "web/x-y/a"]);
+		assert_eq!(page.next_token, None);
+	}
+
+	#[test]
+	fn seek_skips_prefix() {
+		let paths = (0..100).flat_map(|d| (0..10).map(move |t| format!("web/d={d:03}/ts={t}")));
+		let idx = DatapathIndex::new(paths);
+		let rule = Rule::new("web/**").unwrap();
+
+		let mut matches =
+			Matches::new_after(&idx.root, Matcher::new(&rule), &["web", "d=098", "ts=9"]);
+		let rest = matches.by_ref().collect::<Vec<_>>();
+		assert_eq!(rest.len(), 10);
+		assert_eq!(rest[0].0, "web/d=099/ts=0");
+
+		// Only the token's ancestors and the remaining paths
+		assert!(matches.visited() <= 3 + rest.len());
+	}
+
+	#[test]
+	fn key_order_pages() {
+		let paths = vec![
+			"web/ts=10/a",
+			"web/ts=9/a",
+			"web/ts=9/b",
+			"api/ts=100/a",
+			"api/other",
+		];
+		let idx = DatapathIndex::new(paths.into_iter());
+		let options = QueryOptions::new().with_order(QueryOrder::ByKey("ts".to_owned()));
+
+		let page = idx.query_page("**", &options).unwrap();
+		assert_eq!(
+			page.paths,
+			vec![
+				"web/ts=9/a",
+				"web/ts=9/b",
+				"web/ts=10/a",
+				"api/ts=100/a",
+				"api/other"
+			]
+		);
+		assert_eq!(page.next_token, None);
+
+		let pages = all_pages(&idx, "**", options.clone().with_limit(2));
+		assert_eq!(pages.len(), 3);
+		assert_eq!(pages.concat(), page.paths);
+	}
+
+	#[test]
+	fn mixed_key_values() {
+		let paths = vec![
+			"web/ts=2/a",
+			"web/ts=10/a",
+			"web/ts=1a/a",
+			"web/ts=10.0/a",
+			"web/ts=1e1/a",
+			"web/ts=b/a",
+			"web/ts=-1/a",
+			"web/x/a",
+		];
+		let idx = DatapathIndex::new(paths.into_iter());
+		let options = QueryOptions::new().with_order(QueryOrder::ByKey("ts".to_owned()));
+
+		let all = idx.query_page("**", &options).unwrap().paths;
+		assert_eq!(
+			all,
+			vec![
+				"web/ts=-1/a",
+				"web/ts=2/a",
+				"web/ts=10/a",
+				"web/ts=10.0/a",
+				"web/ts=1e1/a",
+				"web/ts=1a/a",
+				"web/ts=b/a",
+				"web/x/a",
+			]
+		);
+
+		// Every page size gives every path exactly once
+		for limit in 1..=all.len() + 1 {
+			let pages = all_pages(&idx, "**", options.clone().with_limit(limit));
+			assert!(pages.iter().all(|x| x.len() <= limit));
+			assert_eq!(pages.concat(), all, "{limit}");
+		}
+	}
+
+	#[test]
+	fn zero_limit() {
+		let idx = DatapathIndex::new(["a/b", "a/c"].into_iter());
+		for order in [QueryOrder::Lexicographic, QueryOrder::ByKey("x".to_owned())] {
+			let options = QueryOptions::new().with_order(order).with_limit(0);
+			assert!(idx.query_page("**", &options).is_none());
+		}
+	}
+
+	#[test]
+	fn invalid_tokens() {
+		let idx = DatapathIndex::new(["a/b", "a/c"].into_iter());
+		let options = QueryOptions::new().with_limit(1);
+
+		let token = idx.query_page("**", &options).unwrap().next_token.unwrap();
+		assert!(
+			idx.query_page("**", &options.clone().with_continuation_token(&token))
+				.is_some()
+		);
+
+		// Tokens from another order don't work
+		let by_key = options
+			.clone()
+			.with_order(QueryOrder::ByKey("x".to_owned()))
+			.with_continuation_token(&token);
+		assert!(idx.query_page("**", &by_key).is_none());
+
+		for bad in ["zz", "abc", "00"] {
+			let bad = options.clone().with_continuation_token(bad);
+			assert!(idx.query_page("**", &bad).is_none());
+		}
+	}
+}
@@ -0,0 +1,147 @@
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+
+use crate::{
+	DatapathIndex, ObjectMeta,
+	index::trie::{Interner, Node},
+};
+
+impl DatapathIndex {
+	/// Like [Self::new], but splits and inserts paths on all threads.
+	///
+	/// The result is identical to [Self::new] with the same paths.
+	pub fn par_new<S, I>(paths: I) -> Self
+	where
+		S: Into<String> + Send,
+		I: IntoParallelIterator<Item = S>,
+		I::Iter: IndexedParallelIterator,
+	{
+		Self::par_new_with_meta(paths.into_par_iter().map(|s| (s, ObjectMeta::default())))
+	}
+
+	/// Like [Self::new_with_meta], but splits and inserts paths on all threads.
+	///
+	/// Each thread builds a trie from a contiguous run of `objects`,
+	/// and these are merged in order. If a path is given more than once,
+	/// its last metadata is kept, so the result is identical to
+	/// [Self::new_with_meta] with the same objects.
+	pub fn par_new_with_meta<S, I>(objects: I) -> Self
+	where
+		S: Into<String> + Send,
+		I: IntoParallelIterator<Item = (S, ObjectMeta)>,
+		I::Iter: IndexedParallelIterator,
+	{
+		let (root, segments) = objects
+			.into_par_iter()
+			.fold(
+				|| (Node::default(), Interner::default()),
+				|(mut node, mut interner), (s, meta)| {
+					let s = s.into();
+					let segments = s.split('/').collect::<Vec<_>>();
+					node.insert(&segments, meta, &mut interner);
+					(node, interner)
+				},
+			)
+			// Indexed iterators reduce adjacent runs, so later runs are merged last
+			.reduce(
+				|| (Node::default(), Interner::default()),
+				|(mut a, mut a_interner), (b, b_interner)| {
+					// `b`'s edges all come from `b_interner`, so we can keep both
+					if a.count() == 0 {
+						return (b, b_interner);
+					}
+
+					// Otherwise, `b`'s segments are re-interned with `a_interner`
+					a.merge(b, &mut a_interner);
+					(a, a_interner)
+				},
+			);
+
+		Self {
+			root,
+			segments,
+			..Self::new_empty()
+		}
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod parallel_tests {
+	use super::*;
+
+	fn objects(index: &DatapathIndex) -> Vec<(String, ObjectMeta)> {
+		index
+			.query_with_meta("**")
+			.unwrap()
+			.map(|(k, v)| (k, v.clone()))
+			.collect()
+	}
+
+	#[test]
+	fn same_as_new() {
+		let input = (0..20_000)
+			.map(|i| {
+				let path = format!("web/domain=d{}.com/ts={}/data.json", i % 997, i % 13);
+				let meta = ObjectMeta {
+					size: Some(i),
+					..Default::default()
+				};
+				(path, meta)
+			})
+			.collect::<Vec<_>>();
+
+		let serial = DatapathIndex::new_with_meta(input.clone().into_iter());
+		let parallel = DatapathIndex::par_new_with_meta(input);
+		assert_eq!(parallel.len(), serial.len());
+		assert_eq!(objects(&parallel), objects(&serial));
+
+		// Later duplicates win, like in `new_with_meta`
+		let meta = parallel
+			.get_meta("web/domain=d0.com/ts=0/data.json")
+			.unwrap();
+		assert_eq!(meta.size, Some(12_961));
+
+		let paths = vec!["a/b", "a", "a/b", "", "c//d"];
+		let serial = DatapathIndex::new(paths.clone().into_iter());
+		let parallel = DatapathIndex::par_new(paths);
+		assert_eq!(parallel.len(), 4);
+		assert_eq!(objects(&parallel), objects(&serial));
+	}
+
+	#[test]
+	fn shared_segments() {
+		let paths = (0..20_000)
+			.map(|i| format!("web/domain=d{}.com/ts={}/data.json", i % 997, i % 13))
+			.collect::<Vec<_>>();
+
+		let mut serial = DatapathIndex::new(paths.clone().into_iter());
+		// Use several threads, so that tries are merged even on one core
+		let pool = rayon::ThreadPoolBuilder::new()
+			.num_threads(4)
+			.build()
+			.unwrap();
+		let mut parallel = pool.install(|| DatapathIndex::par_new(paths.clone()));
+		assert_eq!(
+			parallel.memory_usage().segments,
+			serial.memory_usage().segments
+		);
+
+		// Segments from every thread are shared, and freed once nothing uses them
+		for (i, path) in paths.iter().enumerate() {
+			serial.remove(path);
+			parallel.remove(path);
+			if i % 1000 == 0 {
+				assert_eq!(
+					parallel.memory_usage().segments,
+					serial.memory_usage().segments
+				);
+			}
+		}
+		assert!(parallel.is_empty());
+		assert_eq!(serial.memory_usage().segments, 0);
+		assert_eq!(
+			parallel.memory_usage().segments,
+			serial.memory_usage().segments
+		);
+	}
+}
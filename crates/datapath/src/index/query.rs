@@ -0,0 +1,606 @@
+use std::{
+	borrow::{Borrow, Cow},
+	sync::Arc,
+};
+
+use crate::{
+	ObjectMeta,
+	index::{
+		range::ValueRange,
+		rule::{PatternSegment, Rule},
+		trie::{ChildIter, Node, is_hidden},
+	},
+};
+
+//
+// MARK: matcher
+//
+
+/// A set of positions in a [Rule]'s segments.
+///
+/// Position `i` means "the next path segment must match `segments[i]`",
+/// and position `segments.len()` means "the path matched".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StateSet(u64);
+
+impl StateSet {
+	pub fn is_empty(self) -> bool {
+		self.0 == 0
+	}
+
+	fn contains(self, i: usize) -> bool {
+		self.0 & (1 << i) != 0
+	}
+
+	/// Iterate over the positions in this set, in ascending order
+	fn iter(self) -> impl Iterator<Item = usize> {
+		let mut bits = self.0;
+		std::iter::from_fn(move || {
+			if bits == 0 {
+				return None;
+			}
+
+			let i = bits.trailing_zeros() as usize;
+			bits &= bits - 1;
+			Some(i)
+		})
+	}
+}
+
+/// Matches a [Rule] against a segment trie, one segment at a time.
+///
+/// This walks the rule's segments like an nfa, so that literal segments
+/// are looked up directly, `key=*` segments only scan children with that
+/// key, and `**` can match at any depth. Patterns with too many segments
+/// to track fall back to visiting every node and checking the rule's regex.
+///
+/// If hiding is enabled (see [Self::hiding]), wildcards don't match hidden
+/// segments. Like in a shell, only literals (and globs that start like
+/// a hidden segment, like `_tmp*`) do.
+#[derive(Debug)]
+pub(crate) struct Matcher<R: Borrow<Rule>> {
+	rule: R,
+
+	/// If `true`, wildcards don't match hidden segments
+	hide: bool,
+
+	/// A marker name to hide along with hidden segments.
+	/// `None` if it is hidden already, like `_SUCCESS`.
+	marker: Option<Arc<str>>,
+
+	/// Positions that hold a `**`
+	doublestars: StateSet,
+
+	/// Every position except the accepting one
+	active: u64,
+
+	/// Every segment at or after this position is a `**`
+	all_from: usize,
+
+	/// If `true`, this rule is too long to track and we check its regex instead
+	fallback: bool,
+}
+
+impl<R: Borrow<Rule>> Matcher<R> {
+	pub fn new(rule: R) -> Self {
+		let segments = rule.borrow().segments();
+		let fallback = segments.len() >= 64;
+
+		let mut doublestars = 0;
+		let mut active = 0;
+		let mut all_from = segments.len();
+		if !fallback {
+			for (i, seg) in segments.iter().enumerate() {
+				active |= 1 << i;
+				if matches!(seg, PatternSegment::DoubleStar) {
+					doublestars |= 1 << i;
+				}
+			}
+
+			while all_from > 0 && matches!(segments[all_from - 1], PatternSegment::DoubleStar) {
+				all_from -= 1;
+			}
+		}
+
+		Self {
+			rule,
+			hide: false,
+			marker: None,
+			doublestars: StateSet(doublestars),
+			active,
+			all_from,
+			fallback,
+		}
+	}
+
+	/// Don't match hidden segments or segments named `marker` with wildcards
+	pub fn hiding(mut self, marker: Option<&Arc<str>>) -> Self {
+		self.hide = true;
+		self.marker = marker.filter(|x| !is_hidden(x)).cloned();
+		self
+	}
+
+	pub fn rule(&self) -> &Rule {
+		self.rule.borrow()
+	}
+
+	/// Returns `true` if wildcards can't match `segment`
+	fn is_hidden(&self, segment: &str) -> bool {
+		self.hide && (is_hidden(segment) || self.marker.as_deref() == Some(segment))
+	}
+
+	/// Returns `true` if `pattern` may match hidden segments
+	fn matches_hidden(pattern: &PatternSegment) -> bool {
+		match pattern {
+			PatternSegment::Literal(_) => true,
+			PatternSegment::Glob { prefix, .. } => is_hidden(prefix),
+			PatternSegment::DoubleStar => false,
+		}
+	}
+
+	fn segments(&self) -> &[PatternSegment] {
+		self.rule.borrow().segments()
+	}
+
+	fn accept(&self) -> usize {
+		self.segments().len()
+	}
+
+	/// Add every position reachable by skipping a `**`
+	fn closure(&self, mut states: u64) -> StateSet {
+		// Ascending order handles runs of `**` separated by nothing
+		for i in self.doublestars.iter() {
+			if states & (1 << i) != 0 {
+				states |= 1 << (i + 1);
+			}
+		}
+		StateSet(states)
+	}
+
+	/// The states at the root of a trie
+	pub fn start(&self) -> StateSet {
+		if self.fallback {
+			return StateSet(u64::MAX);
+		}
+
+		self.closure(1)
+	}
+
+	/// The states after consuming the path segment `segment`
+	pub fn step(&self, states: StateSet, segment: &str) -> StateSet {
+		if self.fallback {
+			return states;
+		}
+
+		let segments = self.segments();
+		let hidden = self.is_hidden(segment);
+		let mut next = 0;
+		for i in StateSet(states.0 & self.active).iter() {
+			match &segments[i] {
+				seg if hidden && !Self::matches_hidden(seg) => {}
+
+				// `**` consumes this segment and stays put
+				PatternSegment::DoubleStar => next |= 1 << i,
+				seg if seg.is_match(segment) => next |= 1 << (i + 1),
+				_ => {}
+			}
+		}
+
+		self.closure(next)
+	}
+
+	/// Returns `true` if a path that ends in `states` may match.
+	/// [Self::verify] must also pass.
+	pub fn accepts(&self, states: StateSet) -> bool {
+		self.fallback || states.contains(self.accept())
+	}
+
+	/// Returns `true` if every path below (and including) a node
+	/// with `states` matches.
+	fn accepts_all(&self, states: StateSet) -> bool {
+		!self.fallback && (states.0 & self.active) >> self.all_from != 0
+	}
+
+	/// Like [Self::accepts_all], but also checks that
+	/// `node` has no paths that we hide.
+	pub fn accepts_subtree(&self, node: &Node, states: StateSet) -> bool {
+		self.accepts_all(states) && (!self.hide || (self.marker.is_none() && !node.has_hidden()))
+	}
+
+	/// Returns `true` if a child of a node with `states` may match
+	pub fn can_continue(&self, states: StateSet) -> bool {
+		self.fallback || states.0 & self.active != 0
+	}
+
+	/// Returns `true` if we fell back to regex matching.
+	/// Only then does [Self::verify] need to see paths.
+	pub fn is_fallback(&self) -> bool {
+		self.fallback
+	}
+
+	/// Final check for a path accepted by [Self::accepts].
+	/// This is free unless we fell back to regex matching.
+	pub fn verify(&self, path: &[&str]) -> bool {
+		if !self.fallback {
+			return true;
+		}
+
+		// We can't tell which segments matched hidden segments,
+		// so only allow those that the rule names.
+		let shown = path.iter().all(|seg| {
+			!self.is_hidden(seg)
+				|| self
+					.segments()
+					.iter()
+					.any(|x| matches!(x, PatternSegment::Literal(l) if l == seg))
+		});
+
+		shown && self.rule().is_match(&path.join("/"))
+	}
+
+	/// Select the children of `node` that may match with `states`
+	pub fn children<'a>(&self, node: &'a Node, states: StateSet) -> Children<'a> {
+		if self.fallback {
+			return Children::All(node.children.iter());
+		}
+
+		let active = states.0 & self.active;
+		if active.count_ones() != 1 {
+			return Children::All(node.children.iter());
+		}
+		let i = active.trailing_zeros() as usize;
+
+		match &self.segments()[i] {
+			PatternSegment::Literal(x) => Children::One(node.children.get_key_value(x)),
+
+			PatternSegment::Glob { prefix, .. } if !prefix.is_empty() => {
+				Children::Prefix(node.children.iter_from(prefix), i)
+			}
+
+			_ => Children::All(node.children.iter()),
+		}
+	}
+
+	/// Like [Self::children], but skip children before `start`
+	pub fn children_from<'a>(&self, node: &'a Node, states: StateSet, start: &str) -> Children<'a> {
+		match self.children(node, states) {
+			Children::All(_) => Children::All(node.children.iter_from(start)),
+			Children::One(x) => Children::One(x.filter(|(k, _)| *k >= start)),
+			Children::Prefix(_, i) => {
+				let PatternSegment::Glob { prefix, .. } = &self.segments()[i] else {
+					unreachable!("prefix children are only created for globs")
+				};
+				Children::Prefix(node.children.iter_from(prefix.as_str().max(start)), i)
+			}
+		}
+	}
+
+	/// Get the next child from `children`
+	pub fn next_child<'a>(&self, children: &mut Children<'a>) -> Option<(&'a str, &'a Node)> {
+		match children {
+			Children::All(iter) => iter.next(),
+			Children::One(x) => x.take(),
+			Children::Prefix(iter, i) => {
+				let PatternSegment::Glob { prefix, .. } = &self.segments()[*i] else {
+					unreachable!("prefix children are only created for globs")
+				};
+
+				let (k, v) = iter.next()?;
+				if k.starts_with(prefix.as_str()) {
+					Some((k, v))
+				} else {
+					// Children are sorted, so no later child can match
+					*children = Children::One(None);
+					None
+				}
+			}
+		}
+	}
+}
+
+/// The children of a node that a [Matcher] visits
+#[derive(Debug)]
+pub(crate) enum Children<'a> {
+	/// Every child
+	All(ChildIter<'a>),
+
+	/// At most one child, for a literal segment
+	One(Option<(&'a str, &'a Node)>),
+
+	/// Children that start with the prefix of the glob segment at this index
+	Prefix(ChildIter<'a>, usize),
+}
+
+//
+// MARK: iterator
+//
+
+/// A lazy iterator over all paths in a trie that match a [Rule]
+#[derive(Debug)]
+pub(crate) struct Matches<'a, R: Borrow<Rule>> {
+	matcher: Matcher<R>,
+
+	/// The children we have yet to visit at each depth
+	stack: Vec<(Children<'a>, StateSet)>,
+
+	/// The segments of the last node we visited
+	path: Vec<&'a str>,
+
+	/// If set, only yield paths with a value of this key inside this range
+	range: Option<ValueRange>,
+
+	/// The number of trie nodes we have visited
+	visited: usize,
+
+	/// The number of objects we have visited
+	scanned: usize,
+
+	/// The number of objects whose path matched the rule's segments
+	candidates: usize,
+}
+
+impl<'a, R: Borrow<Rule>> Matches<'a, R> {
+	pub fn new(root: &'a Node, matcher: Matcher<R>) -> Self {
+		let start = matcher.start();
+		let stack = vec![(matcher.children(root, start), start)];
+
+		Self {
+			matcher,
+			stack,
+			path: Vec::new(),
+			range: None,
+			visited: 0,
+			scanned: 0,
+			candidates: 0,
+		}
+	}
+
+	/// Like [Self::new], but only yield paths after the path with segments `after`.
+	/// Paths before it are skipped without visiting them.
+	pub fn new_after(root: &'a Node, matcher: Matcher<R>, after: &[&str]) -> Self {
+		let mut out = Self::new(root, matcher);
+		out.stack.clear();
+
+		let mut node = root;
+		let mut states = out.matcher.start();
+		for seg in after {
+			let mut children = out.matcher.children_from(node, states, seg);
+			let Some((key, child)) = out.matcher.next_child(&mut children) else {
+				return out;
+			};
+
+			if key != *seg {
+				// `after` isn't in this index, the rest of this node comes after it
+				let children = out.matcher.children_from(node, states, seg);
+				out.stack.push((children, states));
+				return out;
+			}
+
+			// Everything after `key` comes after `after`
+			out.stack.push((children, states));
+
+			// `key` itself is a prefix of `after`, so we skip it
+			let next = out.matcher.step(states, key);
+			if next.is_empty() || !out.matcher.can_continue(next) {
+				return out;
+			}
+
+			out.path.push(key);
+			node = child;
+			states = next;
+		}
+
+		// The children of `after` come after it
+		out.stack.push((out.matcher.children(node, states), states));
+		return out;
+	}
+
+	/// Only yield paths that contain a value inside `range`.
+	/// Subtrees with values outside of `range` are skipped.
+	pub fn with_range(mut self, range: ValueRange) -> Self {
+		self.range = Some(range);
+		self
+	}
+
+	/// The number of trie nodes this iterator has visited so far
+	pub fn visited(&self) -> usize {
+		self.visited
+	}
+
+	/// The number of objects this iterator has visited so far
+	pub fn scanned(&self) -> usize {
+		self.scanned
+	}
+
+	/// The number of objects this iterator has visited so far that
+	/// matched the rule's segments, before [Matcher::verify].
+	pub fn candidates(&self) -> usize {
+		self.candidates
+	}
+
+	/// Returns `true` if this iterator checks every path against the rule's regex
+	pub fn is_fallback(&self) -> bool {
+		self.matcher.fallback
+	}
+}
+
+impl<'a, R: Borrow<Rule>> Matches<'a, R> {
+	/// Advance to the next matching path, and borrow it
+	pub fn next_ref(&mut self) -> Option<(PathRef<'_>, &'a ObjectMeta)> {
+		self.next_within(usize::MAX).flatten()
+	}
+
+	/// Like [Self::next_ref], but visit at most `budget` trie nodes.
+	/// Returns `None` if the budget ran out before we found a match,
+	/// and `Some(None)` if there are no more matches.
+	pub fn next_within(&mut self, budget: usize) -> Option<Option<(PathRef<'_>, &'a ObjectMeta)>> {
+		let stop = self.visited.saturating_add(budget);
+		loop {
+			if self.visited >= stop {
+				return None;
+			}
+
+			let depth = self.stack.len();
+			let Some((children, states)) = self.stack.last_mut() else {
+				return Some(None);
+			};
+			let states = *states;
+
+			let Some((seg, node)) = self.matcher.next_child(children) else {
+				self.stack.pop();
+				continue;
+			};
+			self.visited += 1;
+
+			let next = self.matcher.step(states, seg);
+			if next.is_empty() {
+				continue;
+			}
+
+			if let Some(range) = &self.range
+				&& range.check(seg) == Some(false)
+			{
+				continue;
+			}
+
+			self.path.truncate(depth - 1);
+			self.path.push(seg);
+
+			if !node.children.is_empty() && self.matcher.can_continue(next) {
+				let children = self.matcher.children(node, next);
+				self.stack.push((children, next));
+			}
+
+			let Some(meta) = node.object() else {
+				continue;
+			};
+
+			self.scanned += 1;
+			if !self.matcher.accepts(next) {
+				continue;
+			}
+
+			self.candidates += 1;
+			if self.matcher.verify(&self.path)
+				&& self
+					.range
+					.as_ref()
+					.is_none_or(|range| self.path.iter().any(|seg| range.check(seg).is_some()))
+			{
+				return Some(Some((PathRef::new(&self.path), meta)));
+			}
+		}
+	}
+}
+
+impl<'a, R: Borrow<Rule>> Iterator for Matches<'a, R> {
+	type Item = (String, &'a ObjectMeta);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.next_ref()
+			.map(|(path, meta)| (path.segments.join("/"), meta))
+	}
+}
+
+//
+// MARK: borrowed
+//
+
+/// A path in a [crate::DatapathIndex], borrowed from the index's segments.
+///
+/// Paths aren't stored as whole strings, so this holds their
+/// `/`-separated segments instead. It is only allocated as a
+/// `String` if you ask for one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PathRef<'a> {
+	segments: &'a [&'a str],
+}
+
+impl<'a> PathRef<'a> {
+	fn new(segments: &'a [&'a str]) -> Self {
+		Self { segments }
+	}
+
+	/// The `/`-separated segments of this path
+	pub fn segments(&self) -> &'a [&'a str] {
+		self.segments
+	}
+
+	/// The length of this path in bytes, as a string
+	pub fn len(&self) -> usize {
+		self.segments.iter().map(|x| x.len()).sum::<usize>() + self.segments.len() - 1
+	}
+
+	/// Returns `true` if this is the empty path.
+	/// It has one empty segment.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Iterate over the bytes of this path, as a string
+	fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+		self.segments.iter().enumerate().flat_map(|(i, seg)| {
+			let sep = (i != 0).then_some(b'/');
+			sep.into_iter().chain(seg.bytes())
+		})
+	}
+}
+
+impl std::fmt::Display for PathRef<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		for (i, seg) in self.segments.iter().enumerate() {
+			if i != 0 {
+				f.write_str("/")?;
+			}
+			f.write_str(seg)?;
+		}
+		Ok(())
+	}
+}
+
+impl From<PathRef<'_>> for String {
+	fn from(value: PathRef<'_>) -> Self {
+		value.segments.join("/")
+	}
+}
+
+impl PartialEq<str> for PathRef<'_> {
+	fn eq(&self, other: &str) -> bool {
+		self.len() == other.len() && self.bytes().eq(other.bytes())
+	}
+}
+
+impl PartialEq<&str> for PathRef<'_> {
+	fn eq(&self, other: &&str) -> bool {
+		self == *other
+	}
+}
+
+/// A query over a [crate::DatapathIndex] that borrows each path it finds.
+///
+/// This is a lending iterator: each path borrows this cursor,
+/// and is only valid until the next call to [Self::next_path].
+///
+/// An index doesn't store whole paths, only their segments, and each
+/// segment is shared by every path that has it. There is no `&str` of
+/// a whole path to borrow, so paths are returned as a [PathRef] to the
+/// segments this cursor is at. Use [PathRef::segments] to read them
+/// without allocating, or convert a path to a `String` to keep it.
+#[derive(Debug)]
+pub struct QueryCursor<'a> {
+	matches: Matches<'a, Cow<'a, Rule>>,
+}
+
+impl<'a> QueryCursor<'a> {
+	pub(crate) fn new(root: &'a Node, matcher: Matcher<Cow<'a, Rule>>) -> Self {
+		Self {
+			matches: Matches::new(root, matcher),
+		}
+	}
+
+	/// Get the next matching path and its metadata.
+	/// Paths are returned in the same order as [crate::DatapathIndex::query].
+	pub fn next_path(&mut self) -> Option<(PathRef<'_>, &'a ObjectMeta)> {
+		self.matches.next_ref()
+	}
+}
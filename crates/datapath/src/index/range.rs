@@ -0,0 +1,246 @@
+use std::{
+	cmp::Ordering,
+	ops::{Bound, RangeBounds},
+};
+
+/// A partition value, parsed for comparison
+#[derive(Debug, Clone, Copy)]
+enum Value<'a> {
+	Int(i128),
+
+	/// Always finite
+	Float(f64),
+	Text(&'a str),
+}
+
+impl<'a> Value<'a> {
+	fn parse(value: &'a str) -> Self {
+		if let Ok(x) = value.parse::<i128>() {
+			return Self::Int(x);
+		}
+
+		match value.parse::<f64>() {
+			Ok(x) if x.is_finite() => Self::Float(x),
+			_ => Self::Text(value),
+		}
+	}
+}
+
+/// Returns `true` if `value` parses as a finite number
+pub(crate) fn is_number(value: &str) -> bool {
+	!matches!(Value::parse(value), Value::Text(_))
+}
+
+/// Compare an integer and a float exactly
+fn compare_int_float(a: i128, b: f64) -> Ordering {
+	// This is exactly 2^127, which is past the end of `i128`
+	const LIMIT: f64 = i128::MAX as f64;
+	if b >= LIMIT {
+		return Ordering::Less;
+	} else if b < -LIMIT {
+		return Ordering::Greater;
+	}
+
+	let floor = b.floor();
+	match a.cmp(&(floor as i128)) {
+		Ordering::Equal if b > floor => Ordering::Less,
+		x => x,
+	}
+}
+
+/// Compare two partition values, treating values
+/// that are the same number (like `10` and `10.0`) as equal.
+///
+/// Numbers come first and are compared exactly,
+/// then everything else is compared as strings.
+fn compare_numeric(a: &str, b: &str) -> Ordering {
+	match (Value::parse(a), Value::parse(b)) {
+		(Value::Int(a), Value::Int(b)) => a.cmp(&b),
+		(Value::Float(a), Value::Float(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+		(Value::Int(a), Value::Float(b)) => compare_int_float(a, b),
+		(Value::Float(a), Value::Int(b)) => compare_int_float(b, a).reverse(),
+		(Value::Text(a), Value::Text(b)) => a.cmp(b),
+		(Value::Text(_), _) => Ordering::Greater,
+		(_, Value::Text(_)) => Ordering::Less,
+	}
+}
+
+/// Compare two partition values.
+///
+/// Values that parse as numbers come first, and are compared numerically.
+/// Everything else comes after them, and is compared as strings.
+/// Values that are the same number (like `10` and `10.0`) are compared as strings,
+/// so this is a total order: only equal values are [Ordering::Equal].
+pub(crate) fn compare_values(a: &str, b: &str) -> Ordering {
+	compare_numeric(a, b).then_with(|| a.cmp(b))
+}
+
+/// A range of values of one partition key,
+/// like `ts` in `1700000000..1700086400`.
+#[derive(Debug, Clone)]
+pub(crate) struct ValueRange {
+	key: String,
+	start: Bound<String>,
+	end: Bound<String>,
+}
+
+impl ValueRange {
+	pub fn new<V: ToString>(key: &str, range: impl RangeBounds<V>) -> Self {
+		Self {
+			key: key.to_owned(),
+			start: range.start_bound().map(|x| x.to_string()),
+			end: range.end_bound().map(|x| x.to_string()),
+		}
+	}
+
+	/// Returns `true` if `value` is inside this range.
+	/// Values that are the same number as a bound (like `10.0` and `10`) are equal to it.
+	pub fn contains(&self, value: &str) -> bool {
+		let after_start = match &self.start {
+			Bound::Included(x) => compare_numeric(value, x).is_ge(),
+			Bound::Excluded(x) => compare_numeric(value, x).is_gt(),
+			Bound::Unbounded => true,
+		};
+
+		let before_end = match &self.end {
+			Bound::Included(x) => compare_numeric(value, x).is_le(),
+			Bound::Excluded(x) => compare_numeric(value, x).is_lt(),
+			Bound::Unbounded => true,
+		};
+
+		after_start && before_end
+	}
+
+	/// Check one path segment against this range.
+	///
+	/// Returns `None` if this segment isn't a value of our key,
+	/// and `Some(true)` if it is a value inside this range.
+	pub fn check(&self, segment: &str) -> Option<bool> {
+		let (key, value) = segment.split_once('=')?;
+		(key == self.key).then(|| self.contains(value))
+	}
+}
+
+#[cfg(test)]
+mod range_tests {
+	use super::*;
+
+	#[test]
+	fn numeric_and_lexical() {
+		assert!(compare_values("9", "10").is_lt());
+		assert!(compare_values("-5", "3").is_lt());
+		assert!(compare_values("1.5", "10").is_lt());
+		assert!(compare_values("1e3", "999").is_gt());
+		assert!(compare_values("2024-01-02", "2024-01-10").is_lt());
+		assert!(compare_values("b", "a").is_gt());
+
+		// Numbers come before everything else
+		assert!(compare_values("9", "a").is_lt());
+		assert!(compare_values("nan", "1").is_gt());
+		assert!(compare_values("inf", "1").is_gt());
+	}
+
+	#[test]
+	fn total_order() {
+		// These used to form a cycle: 2 < 10 < 1a < 2
+		assert!(compare_values("2", "10").is_lt());
+		assert!(compare_values("10", "1a").is_lt());
+		assert!(compare_values("2", "1a").is_lt());
+
+		// The same number, written differently
+		assert!(compare_values("10", "10.0").is_lt());
+		assert!(compare_values("10.0", "10").is_gt());
+		assert!(compare_values("10", "10").is_eq());
+
+		// Integers and floats are compared exactly
+		assert!(compare_values("170141183460469231731687303715884105727", "1e39").is_lt());
+		assert!(compare_values("-170141183460469231731687303715884105728", "-1e39").is_gt());
+		assert!(compare_values("3", "2.5").is_gt());
+		assert!(compare_values("-3", "-2.5").is_lt());
+		assert!(compare_values("9007199254740993", "9007199254740992.0").is_gt());
+
+		let values = [
+			"2",
+			"10",
+			"1a",
+			"10.0",
+			"1e1",
+			"-0",
+			"0",
+			"-0.0",
+			"a",
+			"",
+			"nan",
+			"1.5",
+			"b",
+			"01",
+			"1",
+			"2024-01-01",
+			"1e400",
+		];
+		for a in values {
+			for b in values {
+				assert_eq!(
+					compare_values(a, b),
+					compare_values(b, a).reverse(),
+					"{a} {b}"
+				);
+				assert_eq!(compare_values(a, b).is_eq(), a == b, "{a} {b}");
+				for c in values {
+					if compare_values(a, b).is_lt() && compare_values(b, c).is_lt() {
+						assert!(compare_values(a, c).is_lt(), "{a} {b} {c}");
+					}
+				}
+			}
+		}
+
+		let mut sorted = values.to_vec();
+		sorted.sort_by(|a, b| compare_values(a, b));
+		assert_eq!(
+			sorted,
+			vec![
+				"-0",
+				"-0.0",
+				"0",
+				"01",
+				"1",
+				"1.5",
+				"2",
+				"10",
+				"10.0",
+				"1e1",
+				"",
+				"1a",
+				"1e400",
+				"2024-01-01",
+				"a",
+				"b",
+				"nan",
+			]
+		);
+	}
+
+	#[test]
+	fn bounds() {
+		let range = ValueRange::new("ts", 100..200);
+		assert!(range.contains("100"));
+		assert!(range.contains("199.5"));
+		assert!(!range.contains("200"));
+		assert!(!range.contains("99"));
+
+		// The same number as a bound is equal to it
+		let range = ValueRange::new("ts", 1..=10);
+		assert!(range.contains("10.0"));
+		assert!(range.contains("1e1"));
+		assert!(!range.contains("1a"));
+
+		let range = ValueRange::new("ts", ..="2024-02");
+		assert!(range.contains("2024-01-31"));
+		assert!(!range.contains("2024-03"));
+
+		assert_eq!(range.check("ts=2024-01"), Some(true));
+		assert_eq!(range.check("ts=2025-01"), Some(false));
+		assert_eq!(range.check("date=2025-01"), None);
+		assert_eq!(range.check("web"), None);
+	}
+}
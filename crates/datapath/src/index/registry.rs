@@ -0,0 +1,232 @@
+use std::{
+	any::{Any, TypeId},
+	collections::{HashMap, HashSet},
+	fmt::Debug,
+	marker::PhantomData,
+};
+
+use crate::{
+	Datapath, DatapathFile, DatapathIndex, Rule,
+	index::query::{Matcher, Matches},
+};
+
+/// Get the query that matches every path (and file) of a datapath [Datapath::PATTERN],
+/// replacing each typed partition with a `*`.
+fn pattern_query(pattern: &str) -> String {
+	let mut query = pattern
+		.split('/')
+		.map(|seg| match seg.split_once('=') {
+			Some((key, _ty)) => format!("{key}=*"),
+			None => seg.to_owned(),
+		})
+		.collect::<Vec<_>>()
+		.join("/");
+
+	// `**` also matches paths with an empty file
+	query.push_str("/**");
+	return query;
+}
+
+/// A registered datapath type, with its type erased
+trait Classifier: Debug + Send + Sync {
+	/// Matches all candidate paths of this type
+	fn rule(&self) -> &Rule;
+
+	/// An empty `Vec<DatapathFile<D>>`
+	fn empty(&self) -> Box<dyn Any + Send + Sync>;
+
+	/// Try to parse `path` as this type, and add it to `out` if it parses.
+	/// `out` was made by [Self::empty].
+	fn push(&self, path: &str, out: &mut (dyn Any + Send + Sync)) -> bool;
+}
+
+#[derive(Debug)]
+struct TypedClassifier<D: Datapath> {
+	rule: Rule,
+	_type: PhantomData<fn() -> D>,
+}
+
+impl<D: Datapath> Classifier for TypedClassifier<D> {
+	fn rule(&self) -> &Rule {
+		&self.rule
+	}
+
+	fn empty(&self) -> Box<dyn Any + Send + Sync> {
+		Box::new(Vec::<DatapathFile<D>>::new())
+	}
+
+	fn push(&self, path: &str, out: &mut (dyn Any + Send + Sync)) -> bool {
+		let Some(out) = out.downcast_mut::<Vec<DatapathFile<D>>>() else {
+			return false;
+		};
+
+		match D::parse(path) {
+			Some(file) => {
+				out.push(file);
+				true
+			}
+			None => false,
+		}
+	}
+}
+
+//
+// MARK: registry
+//
+
+/// A set of [Datapath] types that the paths in a [DatapathIndex] can be classified by.
+#[derive(Debug, Default)]
+pub struct DatapathRegistry {
+	types: Vec<(TypeId, Box<dyn Classifier>)>,
+}
+
+impl DatapathRegistry {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add a datapath type to this registry.
+	/// Registering a type more than once does nothing.
+	pub fn register<D: Datapath>(&mut self) -> &mut Self {
+		let id = TypeId::of::<D>();
+		if self.types.iter().any(|(x, _)| *x == id) {
+			return self;
+		}
+
+		let query = pattern_query(D::PATTERN);
+
+		// Patterns made by the macro are always valid
+		#[expect(clippy::unwrap_used)]
+		let rule = Rule::new(query).unwrap();
+
+		self.types.push((
+			id,
+			Box::new(TypedClassifier::<D> {
+				rule,
+				_type: PhantomData,
+			}),
+		));
+		return self;
+	}
+
+	/// Parse every path in `index` as each registered type.
+	///
+	/// Each type's [Datapath::PATTERN] is queried on `index`, so only
+	/// paths that look like that type are parsed. A path may parse as
+	/// more than one type, and is then returned for each of them.
+	///
+	/// Paths that wildcards don't match, like hidden files and markers
+	/// (see [DatapathIndex::with_hidden_files]), are never parsed.
+	/// They are returned as unmatched, like every other path that
+	/// matches no type. Finding those walks the whole index.
+	pub fn classify(&self, index: &DatapathIndex) -> Classification {
+		let mut files = HashMap::new();
+		let mut matched = HashSet::new();
+
+		for (id, classifier) in &self.types {
+			let mut out = classifier.empty();
+			for path in index.query_rule(classifier.rule()) {
+				if classifier.push(&path, out.as_mut()) {
+					matched.insert(path);
+				}
+			}
+			files.insert(*id, out);
+		}
+
+		// Don't hide anything here, so that hidden files are unmatched
+		#[expect(clippy::unwrap_used)]
+		let all = Rule::new("**").unwrap();
+		let unmatched = Matches::new(&index.root, Matcher::new(&all))
+			.map(|(path, _)| path)
+			.filter(|path| !matched.contains(path))
+			.collect();
+
+		Classification { files, unmatched }
+	}
+}
+
+/// The paths in a [DatapathIndex], grouped by type.
+/// Returned by [DatapathRegistry::classify].
+#[derive(Debug)]
+pub struct Classification {
+	files: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+	unmatched: Vec<String>,
+}
+
+impl Classification {
+	/// Get the paths that parsed as `D`, in order.
+	/// Empty if `D` wasn't registered.
+	pub fn files<D: Datapath>(&self) -> &[DatapathFile<D>] {
+		self.files
+			.get(&TypeId::of::<D>())
+			.and_then(|x| x.downcast_ref::<Vec<DatapathFile<D>>>())
+			.map(|x| x.as_slice())
+			.unwrap_or(&[])
+	}
+
+	/// Get the paths that didn't parse as any registered type, in order
+	pub fn unmatched(&self) -> &[String] {
+		&self.unmatched
+	}
+}
+
+#[cfg(test)]
+mod registry_tests {
+	use super::*;
+	use crate::index::test_paths::{CaptureRaw, WebPage};
+	#[test]
+	fn pattern_queries() {
+		assert_eq!(
+			pattern_query(CaptureRaw::PATTERN),
+			"capture/user_id=*/ts=*/raw/**"
+		);
+	}
+
+	#[test]
+	fn classify() {
+		let paths = vec![
+			"capture/user_id=a/ts=1/raw/data.json",
+			"capture/user_id=a/ts=2/raw/x/y.json",
+			"capture/user_id=a/ts=2/raw/_SUCCESS",
+			"capture/user_id=b/ts=bad/raw/data.json",
+			"capture/user_id=b/ts=3/other/data.json",
+			"web/domain=a.com/index.html",
+			"web/domain=b.com",
+			"misc/file.txt",
+		];
+		let idx = DatapathIndex::new(paths.into_iter());
+
+		let mut registry = DatapathRegistry::new();
+		registry.register::<CaptureRaw>().register::<WebPage>();
+		registry.register::<CaptureRaw>();
+		let result = registry.classify(&idx);
+
+		let raw = result.files::<CaptureRaw>();
+		assert_eq!(raw.len(), 2);
+		assert_eq!(raw[0].path.user_id, "a");
+		assert_eq!(raw[1].path.ts, 2);
+		assert_eq!(raw[1].file, "x/y.json");
+
+		let web = result
+			.files::<WebPage>()
+			.iter()
+			.map(|x| x.to_string())
+			.collect::<Vec<_>>();
+		assert_eq!(web, vec!["web/domain=a.com/index.html", "web/domain=b.com"]);
+
+		assert_eq!(
+			result.unmatched(),
+			&[
+				"capture/user_id=a/ts=2/raw/_SUCCESS",
+				"capture/user_id=b/ts=3/other/data.json",
+				"capture/user_id=b/ts=bad/raw/data.json",
+				"misc/file.txt",
+			]
+		);
+
+		// Unregistered types have no files
+		let result = DatapathRegistry::new().classify(&idx);
+		assert!(result.files::<WebPage>().is_empty());
+		assert_eq!(result.unmatched().len(), 8);
+	}
+}
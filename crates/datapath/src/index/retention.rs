@@ -0,0 +1,414 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::trace;
+
+use crate::{
+	Datapath, DatapathIndex, Rule,
+	index::{
+		query::{Matcher, Matches},
+		trie::is_hidden,
+	},
+};
+
+/// How the values of a time partition are written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimeFormat {
+	/// Seconds since the unix epoch, like `ts=1700000000`
+	EpochSeconds,
+
+	/// Milliseconds since the unix epoch, like `ts=1700000000000`
+	EpochMillis,
+
+	/// A [chrono::format::strftime] format, like `%Y-%m-%d`.
+	/// Times without a timezone are in UTC,
+	/// and dates without a time are at midnight.
+	Format(String),
+}
+
+impl TimeFormat {
+	/// Parse a partition value.
+	/// Returns `None` if it isn't a time in this format.
+	pub fn parse(&self, value: &str) -> Option<DateTime<Utc>> {
+		match self {
+			Self::EpochSeconds => DateTime::from_timestamp(value.parse().ok()?, 0),
+			Self::EpochMillis => DateTime::from_timestamp_millis(value.parse().ok()?),
+			Self::Format(format) => {
+				if let Ok(x) = DateTime::parse_from_str(value, format) {
+					return Some(x.to_utc());
+				}
+
+				if let Ok(x) = NaiveDateTime::parse_from_str(value, format) {
+					return Some(x.and_utc());
+				}
+
+				let date = NaiveDate::parse_from_str(value, format).ok()?;
+				Some(date.and_hms_opt(0, 0, 0)?.and_utc())
+			}
+		}
+	}
+}
+
+/// Which partitions [DatapathIndex::plan_retention] expires
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+	/// The partition key that holds each partition's time
+	pub key: String,
+
+	/// How the values of `key` are written
+	pub format: TimeFormat,
+
+	/// Partitions strictly older than this expire
+	pub cutoff: DateTime<Utc>,
+
+	/// Never expire the newest `n` partitions of each group.
+	/// See [Self::with_keep_newest].
+	pub keep_newest: Option<usize>,
+}
+
+impl RetentionPolicy {
+	pub fn new(key: impl Into<String>, format: TimeFormat, cutoff: DateTime<Utc>) -> Self {
+		Self {
+			key: key.into(),
+			format,
+			cutoff,
+			keep_newest: None,
+		}
+	}
+
+	/// Always keep the newest `n` partitions in each group, even if they
+	/// are older than the cutoff. A group is all partitions with the same
+	/// path before `key`, so `web/domain=a.com/ts=*` is one group.
+	pub fn with_keep_newest(mut self, n: usize) -> Self {
+		self.keep_newest = Some(n);
+		self
+	}
+}
+
+/// A partition that [DatapathIndex::plan_retention] expired
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpiredPartition {
+	/// The path of this partition, ending with its time key.
+	/// For example, `web/domain=a.com/ts=1700000000`.
+	pub partition: String,
+
+	/// The time of this partition
+	pub time: DateTime<Utc>,
+
+	/// The paths to delete in this partition, in order.
+	/// These are the paths that matched the query, and the hidden files
+	/// and markers (like `_SUCCESS` or `.a.json.crc`) in the same
+	/// directories. Other paths in this partition are kept.
+	pub paths: Vec<String>,
+}
+
+/// A partition found while planning
+struct Partition {
+	/// The segments before the time key
+	group: Vec<String>,
+	partition: String,
+	time: Option<DateTime<Utc>>,
+
+	/// The paths in this partition that matched
+	paths: Vec<String>,
+}
+
+impl DatapathIndex {
+	/// Find the paths that match `query` in partitions
+	/// that expire under `policy`, grouped by partition.
+	///
+	/// This only plans: nothing is removed from this index.
+	/// Partitions with a time that can't be parsed never expire,
+	/// and paths without the time key are ignored.
+	///
+	/// Unlike [Self::query], wildcards here match hidden files and markers
+	/// (see [Self::with_hidden_files]), so that a partition with only hidden
+	/// files expires too. Hidden files and markers next to a matched path are
+	/// deleted with it, so that no orphans are left behind. Nothing else is:
+	/// `web/*/*.json` won't delete `web/ts=1/_temporary/0/a.json`, or another
+	/// dataset in the same partition.
+	///
+	/// Partitions are returned in order.
+	/// Returns `None` if the query was invalid.
+	pub fn plan_retention(
+		&self,
+		query: impl Into<String>,
+		policy: &RetentionPolicy,
+	) -> Option<Vec<ExpiredPartition>> {
+		let rule = Rule::new(query)?;
+		Some(self.plan_retention_rule(&rule, policy))
+	}
+
+	/// Like [Self::plan_retention], but only looks at paths
+	/// that match `path` (see [Datapath::from_wildcardable]).
+	///
+	/// Returns `None` if a value in `path` makes an invalid query, like `***`.
+	pub fn plan_retention_datapath<D: Datapath>(
+		&self,
+		path: D::WildcardableTuple,
+		policy: &RetentionPolicy,
+	) -> Option<Vec<ExpiredPartition>> {
+		let rule = Rule::datapath::<D>(path)?;
+		Some(self.plan_retention_rule(&rule, policy))
+	}
+
+	/// Like [Self::plan_retention], but with a precompiled rule
+	pub fn plan_retention_rule(
+		&self,
+		rule: &Rule,
+		policy: &RetentionPolicy,
+	) -> Vec<ExpiredPartition> {
+		trace!("DatapathIndex query is {}", rule.pattern());
+
+		// Matches are in order, so the paths in each partition are adjacent.
+		// We don't hide anything, so that partitions with only hidden files expire too.
+		let mut partitions: Vec<Partition> = Vec::new();
+		let mut matches = Matches::new(&self.root, Matcher::new(rule));
+		while let Some((path, _)) = matches.next_ref() {
+			let segments = path.segments();
+			let Some(i) = segments
+				.iter()
+				.position(|seg| seg.split_once('=').is_some_and(|(k, _)| k == policy.key))
+			else {
+				continue;
+			};
+
+			let partition = segments[..=i].join("/");
+			if let Some(last) = partitions.last_mut()
+				&& last.partition == partition
+			{
+				last.paths.push(path.to_string());
+				continue;
+			}
+
+			let value = segments[i].split_once('=').map(|(_, v)| v).unwrap_or("");
+			partitions.push(Partition {
+				group: segments[..i].iter().map(|x| (*x).to_owned()).collect(),
+				partition,
+				time: policy.format.parse(value),
+				paths: vec![path.to_string()],
+			});
+		}
+
+		// The partitions in each group, newest first
+		let mut groups: BTreeMap<&[String], Vec<(DateTime<Utc>, usize)>> = BTreeMap::new();
+		for (i, p) in partitions.iter().enumerate() {
+			if let Some(time) = p.time {
+				groups.entry(&p.group).or_default().push((time, i));
+			}
+		}
+
+		let mut expired = vec![false; partitions.len()];
+		for group in groups.values_mut() {
+			group.sort_by(|a, b| b.cmp(a));
+			let keep = policy.keep_newest.unwrap_or(0);
+			for (time, i) in group.iter().skip(keep) {
+				if *time < policy.cutoff
+					&& let Some(x) = expired.get_mut(*i)
+				{
+					*x = true;
+				}
+			}
+		}
+
+		partitions
+			.into_iter()
+			.zip(expired)
+			.filter_map(|(p, expired)| {
+				Some(ExpiredPartition {
+					time: p.time.filter(|_| expired)?,
+					paths: self.with_hidden_neighbors(p.paths),
+					partition: p.partition,
+				})
+			})
+			.collect()
+	}
+
+	/// Add the hidden files and markers in the directories of `paths`.
+	/// `paths` must be in order, and so is the result.
+	fn with_hidden_neighbors(&self, mut paths: Vec<String>) -> Vec<String> {
+		let dirs = paths
+			.iter()
+			.filter_map(|x| x.rsplit_once('/').map(|(dir, _)| dir.to_owned()))
+			.collect::<BTreeSet<_>>();
+
+		let mut neighbors = Vec::new();
+		for dir in dirs {
+			let segments = dir.split('/').collect::<Vec<_>>();
+			let Some(node) = self.root.get(&segments) else {
+				continue;
+			};
+
+			for (name, child) in node.children.iter() {
+				if child.object().is_some() && (is_hidden(name) || name == &*self.marker) {
+					neighbors.push(format!("{dir}/{name}"));
+				}
+			}
+		}
+
+		paths.extend(neighbors);
+		paths.sort_by(|a, b| a.split('/').cmp(b.split('/')));
+		paths.dedup();
+		return paths;
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod retention_tests {
+	use super::*;
+	use crate::{Wildcardable, index::test_paths::CaptureRaw};
+
+	#[test]
+	fn time_formats() {
+		let t = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+		assert_eq!(TimeFormat::EpochSeconds.parse("1700000000"), Some(t));
+		assert_eq!(TimeFormat::EpochMillis.parse("1700000000000"), Some(t));
+		assert_eq!(TimeFormat::EpochSeconds.parse("x"), None);
+
+		let date = TimeFormat::Format("%Y-%m-%d".to_owned());
+		assert_eq!(
+			date.parse("2023-11-14"),
+			Some(DateTime::from_timestamp(1_699_920_000, 0).unwrap())
+		);
+		assert_eq!(date.parse("2023-11"), None);
+
+		let time = TimeFormat::Format("%Y-%m-%dT%H:%M:%S".to_owned());
+		assert_eq!(time.parse("2023-11-14T22:13:20"), Some(t));
+	}
+
+	#[test]
+	fn plan_retention() {
+		let paths = vec![
+			"web/domain=a.com/date=2024-01-01/1.json",
+			"web/domain=a.com/date=2024-01-01/2.json",
+			"web/domain=a.com/date=2024-01-02/1.json",
+			"web/domain=a.com/date=2024-01-03/1.json",
+			"web/domain=a.com/date=2024-02-01/1.json",
+			"web/domain=a.com/date=bad/1.json",
+			"web/domain=b.com/date=2024-01-01/1.json",
+			"web/domain=b.com/other.json",
+		];
+		let idx = DatapathIndex::new(paths.into_iter());
+
+		let cutoff = "2024-01-15T00:00:00Z".parse().unwrap();
+		let policy =
+			RetentionPolicy::new("date", TimeFormat::Format("%Y-%m-%d".to_owned()), cutoff);
+
+		let plan = idx.plan_retention("web/**", &policy).unwrap();
+		let partitions = plan
+			.iter()
+			.map(|x| x.partition.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			partitions,
+			vec![
+				"web/domain=a.com/date=2024-01-01",
+				"web/domain=a.com/date=2024-01-02",
+				"web/domain=a.com/date=2024-01-03",
+				"web/domain=b.com/date=2024-01-01",
+			]
+		);
+		assert_eq!(plan[0].paths.len(), 2);
+
+		// The newest partitions of each domain are kept
+		let plan = idx
+			.plan_retention("web/**", &policy.clone().with_keep_newest(2))
+			.unwrap();
+		let partitions = plan
+			.iter()
+			.map(|x| x.partition.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			partitions,
+			vec![
+				"web/domain=a.com/date=2024-01-01",
+				"web/domain=a.com/date=2024-01-02"
+			]
+		);
+
+		assert!(idx.plan_retention("web/***", &policy).is_none());
+	}
+
+	#[test]
+	fn hidden_files_expire() {
+		let paths = vec![
+			"web/date=2024-01-01/a.json",
+			"web/date=2024-01-01/.a.json.crc",
+			"web/date=2024-01-01/_SUCCESS",
+			"web/date=2024-01-01/_temporary/0/b.json",
+			"web/date=2024-01-02/_SUCCESS",
+			"web/date=2024-02-01/a.json",
+			"web/date=2024-02-01/_SUCCESS",
+		];
+		let idx = DatapathIndex::new(paths.into_iter());
+
+		let cutoff = "2024-01-15T00:00:00Z".parse().unwrap();
+		let policy =
+			RetentionPolicy::new("date", TimeFormat::Format("%Y-%m-%d".to_owned()), cutoff);
+
+		let plan = idx.plan_retention("web/**", &policy).unwrap();
+		assert_eq!(plan.len(), 2);
+		let expected = vec![
+			"web/date=2024-01-01/.a.json.crc",
+			"web/date=2024-01-01/_SUCCESS",
+			"web/date=2024-01-01/_temporary/0/b.json",
+			"web/date=2024-01-01/a.json",
+		];
+		assert_eq!(plan[0].paths, expected);
+
+		// Partitions with only hidden files expire too
+		assert_eq!(plan[1].partition, "web/date=2024-01-02");
+		assert_eq!(plan[1].paths, vec!["web/date=2024-01-02/_SUCCESS"]);
+
+		// Only hidden files next to matches are added
+		let plan = idx.plan_retention("web/*/*.json", &policy).unwrap();
+		assert_eq!(plan.len(), 1);
+		assert_eq!(
+			plan[0].paths,
+			vec![
+				"web/date=2024-01-01/.a.json.crc",
+				"web/date=2024-01-01/_SUCCESS",
+				"web/date=2024-01-01/a.json",
+			]
+		);
+	}
+
+	#[test]
+	fn plan_retention_datapath() {
+		let paths = vec![
+			"capture/user_id=a/ts=100/raw/1.json",
+			"capture/user_id=a/ts=100/raw/_SUCCESS",
+			"capture/user_id=a/ts=100/processed/1.json",
+			"capture/user_id=a/ts=200/raw/1.json",
+			"capture/user_id=b/ts=100/raw/1.json",
+		];
+		let idx = DatapathIndex::new(paths.into_iter());
+
+		let cutoff = DateTime::from_timestamp(150, 0).unwrap();
+		let policy = RetentionPolicy::new("ts", TimeFormat::EpochSeconds, cutoff);
+		let plan = idx
+			.plan_retention_datapath::<CaptureRaw>(
+				(Wildcardable::Value("a".to_owned()), Wildcardable::Star),
+				&policy,
+			)
+			.unwrap();
+
+		assert_eq!(plan.len(), 1);
+		assert_eq!(plan[0].partition, "capture/user_id=a/ts=100");
+		// Other datasets in the same partition are kept
+		assert_eq!(
+			plan[0].paths,
+			vec![
+				"capture/user_id=a/ts=100/raw/1.json",
+				"capture/user_id=a/ts=100/raw/_SUCCESS",
+			]
+		);
+
+		// Values can make invalid queries
+		let plan = idx.plan_retention_datapath::<CaptureRaw>(
+			(Wildcardable::Value("***".to_owned()), Wildcardable::Star),
+			&policy,
+		);
+		assert!(plan.is_none());
+	}
+}
@@ -1,6 +1,13 @@
 use regex::Regex;
+use std::sync::LazyLock;
 use tracing::warn;
 
+use crate::Datapath;
+
+/// Splits a pattern on slashes or runs of stars
+#[expect(clippy::unwrap_used)]
+static SPLIT_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new("[*]{2,}|[/]").unwrap());
+
 //
 // MARK: rule
 //
@@ -52,13 +59,84 @@ impl RegexSegment {
 	}
 }
 
+/// One segment of a [Rule], which matches one segment of a path
+#[derive(Debug, Clone)]
+pub(crate) enum PatternSegment {
+	/// A segment without wildcards, like `web` or `ts=1234`
+	Literal(String),
+
+	/// A segment with at least one `*`, like `ts=*` or `*.json`.
+	///
+	/// `prefix` is everything before the first `*`. `regex` matches
+	/// the whole segment, and is `None` if checking `prefix` is enough
+	/// (as in `ts=*`).
+	Glob {
+		prefix: String,
+		regex: Option<Regex>,
+	},
+
+	/// A `**`, which matches any number of segments
+	DoubleStar,
+}
+
+impl PatternSegment {
+	fn new(segment: &str) -> Self {
+		if segment == "**" {
+			return Self::DoubleStar;
+		}
+
+		let Some((prefix, rest)) = segment.split_once('*') else {
+			return Self::Literal(segment.to_owned());
+		};
+
+		let regex = (!rest.chars().all(|x| x == '*')).then(|| {
+			let re = segment
+				.split('*')
+				.map(regex::escape)
+				.collect::<Vec<_>>()
+				.join("[^/]*");
+
+			// This regex should always be valid
+			#[expect(clippy::unwrap_used)]
+			Regex::new(&format!("^{re}$")).unwrap()
+		});
+
+		Self::Glob {
+			prefix: prefix.to_owned(),
+			regex,
+		}
+	}
+
+	/// Returns `true` if this matches the path segment `segment`.
+	/// Always `true` for [Self::DoubleStar].
+	pub(crate) fn is_match(&self, segment: &str) -> bool {
+		match self {
+			Self::Literal(x) => x == segment,
+			Self::Glob { prefix, regex } => {
+				segment.starts_with(prefix.as_str())
+					&& regex.as_ref().is_none_or(|r| r.is_match(segment))
+			}
+			Self::DoubleStar => true,
+		}
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct Rule {
 	regex: Regex,
 	pattern: String,
+	segments: Vec<PatternSegment>,
 }
 
 impl Rule {
+	/// Make a rule that matches every file in the datapaths
+	/// that match `path` (see [Datapath::from_wildcardable]).
+	///
+	/// Returns `None` if a value makes an invalid query, like `***`.
+	pub(crate) fn datapath<D: Datapath>(path: D::WildcardableTuple) -> Option<Self> {
+		Self::new(format!("{}/**", D::from_wildcardable(path)))
+	}
+
 	pub fn pattern(&self) -> &str {
 		&self.pattern
 	}
@@ -71,19 +149,30 @@ impl Rule {
 		self.regex.is_match(s)
 	}
 
+	/// The segments of this rule's pattern.
+	/// An empty pattern has one empty literal segment.
+	pub(crate) fn segments(&self) -> &[PatternSegment] {
+		&self.segments
+	}
+
 	pub fn raw_regex_str(&self) -> String {
+		// This pattern was validated in `new`
+		#[expect(clippy::unwrap_used)]
 		Self::regex_str(self.pattern()).unwrap()
 	}
 
-	fn regex_str(pattern: &str) -> Option<String> {
+	/// Split a pattern into segments.
+	///
+	/// This is a lot like .split("/"), but handles
+	/// the edge case where ** is not delimited by slashes
+	/// (`root**test` is equivalent to `root/**/test`).
+	/// Consecutive doublestars are reduced to one.
+	///
+	/// Returns `None` if this pattern is invalid.
+	fn split_pattern(pattern: &str) -> Option<Vec<&str>> {
 		// Split on slashes or stars
-		// This is a lot like .split("/"), but handles
-		// the edge case where ** is not delimited by slashes
-		// (`root**test` is equivalent to `root/**/test`)
 		let segments = {
-			#[expect(clippy::unwrap_used)]
-			let re = Regex::new("[*]{2,}|[/]").unwrap();
-			let split = re.find_iter(&pattern);
+			let split = SPLIT_REGEX.find_iter(pattern);
 
 			let bounds = split
 				.into_iter()
@@ -110,25 +199,34 @@ impl Rule {
 			parts
 		};
 
-		let mut rebuilt_segments = Vec::new();
-		let mut last_was_doublestar = false;
+		let mut out: Vec<&str> = Vec::new();
 		for segment in segments {
 			// This is a wildcard regex
 			// (**, ***, etc)
 			if segment.len() > 1 && segment.chars().all(|x| x == '*') {
-				match segment {
-					"**" => {
-						// Consecutive doublestars are meaningless
-						if !last_was_doublestar {
-							rebuilt_segments.push(RegexSegment::DoubleStar);
-						}
-						last_was_doublestar = true;
-					}
-					_ => return None,
+				if segment != "**" {
+					return None;
 				}
+
+				// Consecutive doublestars are meaningless
+				if out.last() == Some(&"**") {
+					continue;
+				}
+			}
+
+			out.push(segment);
+		}
+
+		return Some(out);
+	}
+
+	fn regex_str(pattern: &str) -> Option<String> {
+		let mut rebuilt_segments = Vec::new();
+		for segment in Self::split_pattern(pattern)? {
+			if segment == "**" {
+				rebuilt_segments.push(RegexSegment::DoubleStar);
 				continue;
 			}
-			last_was_doublestar = false;
 
 			let parts = segment.split("*").collect::<Vec<_>>();
 
@@ -174,7 +272,21 @@ impl Rule {
 		#[expect(clippy::unwrap_used)]
 		let regex = Regex::new(&re_built).unwrap();
 
-		Some(Self { regex, pattern })
+		let mut segments = Self::split_pattern(&pattern)?
+			.into_iter()
+			.map(PatternSegment::new)
+			.collect::<Vec<_>>();
+
+		// An empty pattern matches only the empty path
+		if segments.is_empty() {
+			segments.push(PatternSegment::Literal(String::new()));
+		}
+
+		Some(Self {
+			regex,
+			pattern,
+			segments,
+		})
 	}
 }
 
@@ -0,0 +1,704 @@
+use chrono::DateTime;
+use flate2::read::GzDecoder;
+use percent_encoding::percent_decode_str;
+use quick_xml::{
+	escape::resolve_predefined_entity,
+	events::{BytesStart, Event},
+};
+use std::{
+	fmt::Display,
+	fs::File,
+	io::{BufRead, BufReader, Read},
+	path::{Component, Path},
+	time::SystemTime,
+};
+
+use crate::{DatapathIndex, ObjectMeta};
+
+mod events;
+pub use events::{S3Event, S3EventApplier, S3EventKind, read_s3_events};
+
+//
+// MARK: errors
+//
+
+/// An error encountered while loading an s3 listing
+#[derive(Debug)]
+pub enum S3ParseError {
+	/// We could not read our input
+	Io(std::io::Error),
+
+	/// A `ListObjectsV2` response was not valid xml
+	Xml(quick_xml::Error),
+
+	/// An inventory file was not valid csv
+	Csv(csv::Error),
+
+	/// An inventory manifest was not valid json
+	Json(serde_json::Error),
+
+	/// A required field was missing
+	MissingField(&'static str),
+
+	/// A field had a value we could not parse
+	InvalidField { field: &'static str, value: String },
+}
+
+impl Display for S3ParseError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Io(e) => write!(f, "i/o error: {e}"),
+			Self::Xml(e) => write!(f, "invalid xml: {e}"),
+			Self::Csv(e) => write!(f, "invalid csv: {e}"),
+			Self::Json(e) => write!(f, "invalid json: {e}"),
+			Self::MissingField(field) => write!(f, "missing required field `{field}`"),
+			Self::InvalidField { field, value } => {
+				write!(f, "invalid value `{value}` for field `{field}`")
+			}
+		}
+	}
+}
+
+impl std::error::Error for S3ParseError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Io(e) => Some(e),
+			Self::Xml(e) => Some(e),
+			Self::Csv(e) => Some(e),
+			Self::Json(e) => Some(e),
+			Self::MissingField(_) | Self::InvalidField { .. } => None,
+		}
+	}
+}
+
+impl From<std::io::Error> for S3ParseError {
+	fn from(value: std::io::Error) -> Self {
+		Self::Io(value)
+	}
+}
+
+impl From<quick_xml::Error> for S3ParseError {
+	fn from(value: quick_xml::Error) -> Self {
+		Self::Xml(value)
+	}
+}
+
+impl From<csv::Error> for S3ParseError {
+	fn from(value: csv::Error) -> Self {
+		Self::Csv(value)
+	}
+}
+
+impl From<serde_json::Error> for S3ParseError {
+	fn from(value: serde_json::Error) -> Self {
+		Self::Json(value)
+	}
+}
+
+//
+// MARK: helpers
+//
+
+/// Decode a key encoded with `encoding-type=url`.
+/// s3 encodes spaces as `+`, so we do too.
+fn url_decode(field: &'static str, value: &str) -> Result<String, S3ParseError> {
+	let value = value.replace('+', " ");
+	percent_decode_str(&value)
+		.decode_utf8()
+		.map(|x| x.into_owned())
+		.map_err(|_err| S3ParseError::InvalidField { field, value })
+}
+
+fn parse_size(field: &'static str, value: &str) -> Result<u64, S3ParseError> {
+	value
+		.trim()
+		.parse()
+		.map_err(|_err| S3ParseError::InvalidField {
+			field,
+			value: value.to_owned(),
+		})
+}
+
+fn parse_time(field: &'static str, value: &str) -> Result<SystemTime, S3ParseError> {
+	DateTime::parse_from_rfc3339(value.trim())
+		.map(SystemTime::from)
+		.map_err(|_err| S3ParseError::InvalidField {
+			field,
+			value: value.to_owned(),
+		})
+}
+
+/// s3 wraps etags in quotes, we don't store them.
+fn parse_etag(value: &str) -> String {
+	value.trim().trim_matches('"').to_owned()
+}
+
+//
+// MARK: ListObjectsV2
+//
+
+/// One page of an s3 `ListObjectsV2` response
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListObjectsV2Page {
+	/// The bucket that was listed
+	pub name: Option<String>,
+
+	/// The prefix that was listed
+	pub prefix: Option<String>,
+
+	/// If `true`, more pages follow this one
+	pub is_truncated: bool,
+
+	/// The token that was used to request this page
+	pub continuation_token: Option<String>,
+
+	/// The token that should be used to request the next page
+	pub next_continuation_token: Option<String>,
+
+	/// The objects on this page, with url-encoded keys decoded
+	pub contents: Vec<(String, ObjectMeta)>,
+
+	/// The common prefixes on this page, if a delimiter was given
+	pub common_prefixes: Vec<String>,
+}
+
+impl ListObjectsV2Page {
+	/// Parse a `ListObjectsV2` xml response.
+	///
+	/// If the response has `<EncodingType>url</EncodingType>`,
+	/// keys and prefixes are url-decoded.
+	pub fn from_reader<R: BufRead>(reader: R) -> Result<Self, S3ParseError> {
+		let mut reader = quick_xml::Reader::from_reader(reader);
+		let mut buf = Vec::new();
+
+		let mut page = Self::default();
+		let mut url_encoded = false;
+
+		// Names of all open elements
+		let mut stack: Vec<String> = Vec::new();
+		// Text of the innermost open element
+		let mut text = String::new();
+		// The `<Contents>` element we're inside of, if any
+		let mut object: Option<(Option<String>, ObjectMeta)> = None;
+
+		let name_of =
+			|e: &BytesStart<'_>| String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+
+		loop {
+			// Handle empty elements (like `<Prefix/>`) as a start and an end
+			let end = match reader.read_event_into(&mut buf)? {
+				Event::Eof => break,
+
+				Event::Start(e) => {
+					let name = name_of(&e);
+					if name == "Contents" {
+						object = Some((None, ObjectMeta::default()));
+					}
+					stack.push(name);
+					text.clear();
+					None
+				}
+
+				Event::Empty(e) => {
+					stack.push(name_of(&e));
+					text.clear();
+					stack.pop()
+				}
+
+				Event::End(_) => stack.pop(),
+
+				Event::Text(e) => {
+					text.push_str(&e.xml_content().map_err(quick_xml::Error::from)?);
+					None
+				}
+
+				Event::CData(e) => {
+					text.push_str(&e.decode().map_err(quick_xml::Error::from)?);
+					None
+				}
+
+				Event::GeneralRef(e) => {
+					if let Some(c) = e.resolve_char_ref()? {
+						text.push(c);
+					} else {
+						let name = e.decode().map_err(quick_xml::Error::from)?;
+						match resolve_predefined_entity(&name) {
+							Some(x) => text.push_str(x),
+							None => {
+								return Err(S3ParseError::InvalidField {
+									field: "entity",
+									value: name.into_owned(),
+								});
+							}
+						}
+					}
+					None
+				}
+
+				_ => None,
+			};
+			buf.clear();
+
+			let Some(name) = end else { continue };
+			let parent = stack.last().map(|x| x.as_str());
+
+			match (parent, name.as_str()) {
+				(Some("ListBucketResult"), "Name") => page.name = Some(text.clone()),
+				(Some("ListBucketResult"), "Prefix") => page.prefix = Some(text.clone()),
+				(Some("ListBucketResult"), "EncodingType") => url_encoded = text.trim() == "url",
+				(Some("ListBucketResult"), "IsTruncated") => {
+					page.is_truncated = text.trim() == "true"
+				}
+				(Some("ListBucketResult"), "ContinuationToken") => {
+					page.continuation_token = Some(text.clone())
+				}
+				(Some("ListBucketResult"), "NextContinuationToken") => {
+					page.next_continuation_token = Some(text.clone())
+				}
+
+				(Some("ListBucketResult"), "Contents") => {
+					if let Some((key, meta)) = object.take() {
+						let key = key.ok_or(S3ParseError::MissingField("Key"))?;
+						page.contents.push((key, meta));
+					}
+				}
+
+				(Some("Contents"), field) => {
+					if let Some((key, meta)) = object.as_mut() {
+						match field {
+							"Key" => *key = Some(text.clone()),
+							"Size" => meta.size = Some(parse_size("Size", &text)?),
+							"LastModified" => {
+								meta.last_modified = Some(parse_time("LastModified", &text)?)
+							}
+							"ETag" => meta.etag = Some(parse_etag(&text)),
+							_ => {}
+						}
+					}
+				}
+
+				(Some("CommonPrefixes"), "Prefix") => page.common_prefixes.push(text.clone()),
+
+				_ => {}
+			}
+
+			text.clear();
+		}
+
+		if url_encoded {
+			if let Some(prefix) = page.prefix.as_mut() {
+				*prefix = url_decode("Prefix", prefix)?;
+			}
+
+			for (key, _) in &mut page.contents {
+				*key = url_decode("Key", key)?;
+			}
+
+			for prefix in &mut page.common_prefixes {
+				*prefix = url_decode("Prefix", prefix)?;
+			}
+		}
+
+		return Ok(page);
+	}
+}
+
+//
+// MARK: inventory
+//
+
+/// A data file listed in an s3 inventory manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InventoryFile {
+	/// The key of this file in the inventory's destination bucket
+	pub key: String,
+
+	/// The size of this file, in bytes
+	pub size: Option<u64>,
+
+	/// The md5 checksum of this file
+	pub md5: Option<String>,
+}
+
+/// An s3 inventory `manifest.json`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InventoryManifest {
+	/// The bucket this inventory describes
+	pub source_bucket: Option<String>,
+
+	/// The format of each data file (`CSV`, `ORC`, or `Parquet`)
+	pub file_format: String,
+
+	/// The columns of each data file, like `Bucket, Key, Size`
+	pub file_schema: String,
+
+	/// The data files in this inventory
+	pub files: Vec<InventoryFile>,
+}
+
+impl InventoryManifest {
+	/// Parse an s3 inventory `manifest.json`
+	pub fn from_reader<R: Read>(reader: R) -> Result<Self, S3ParseError> {
+		let json: serde_json::Value = serde_json::from_reader(reader)?;
+
+		let str_field = |field: &'static str| -> Result<String, S3ParseError> {
+			json.get(field)
+				.and_then(|x| x.as_str())
+				.map(|x| x.to_owned())
+				.ok_or(S3ParseError::MissingField(field))
+		};
+
+		let files = json
+			.get("files")
+			.and_then(|x| x.as_array())
+			.ok_or(S3ParseError::MissingField("files"))?
+			.iter()
+			.map(|f| {
+				Ok(InventoryFile {
+					key: f
+						.get("key")
+						.and_then(|x| x.as_str())
+						.ok_or(S3ParseError::MissingField("key"))?
+						.to_owned(),
+					size: f.get("size").and_then(|x| x.as_u64()),
+					md5: f
+						.get("MD5checksum")
+						.and_then(|x| x.as_str())
+						.map(|x| x.to_owned()),
+				})
+			})
+			.collect::<Result<Vec<_>, S3ParseError>>()?;
+
+		Ok(Self {
+			source_bucket: str_field("sourceBucket").ok(),
+			file_format: str_field("fileFormat")?,
+			file_schema: str_field("fileSchema")?,
+			files,
+		})
+	}
+}
+
+/// Read the objects in one s3 inventory csv file.
+///
+/// `file_schema` is the `fileSchema` field of this inventory's manifest,
+/// like `Bucket, Key, Size, LastModifiedDate, ETag`. Inventory keys are
+/// always url-encoded, and are decoded here. If the schema has `IsLatest`
+/// or `IsDeleteMarker` columns, only live current versions are returned.
+pub fn read_inventory_csv<R: Read>(
+	reader: R,
+	file_schema: &str,
+) -> Result<Vec<(String, ObjectMeta)>, S3ParseError> {
+	let columns = file_schema.split(',').map(|x| x.trim()).collect::<Vec<_>>();
+	let column = |name: &str| columns.iter().position(|x| *x == name);
+
+	let key_idx = column("Key").ok_or(S3ParseError::MissingField("Key"))?;
+	let size_idx = column("Size");
+	let time_idx = column("LastModifiedDate");
+	let etag_idx = column("ETag");
+	let latest_idx = column("IsLatest");
+	let deleted_idx = column("IsDeleteMarker");
+
+	let mut reader = csv::ReaderBuilder::new()
+		.has_headers(false)
+		.flexible(true)
+		.from_reader(reader);
+
+	let mut out = Vec::new();
+	for record in reader.records() {
+		let record = record?;
+		let get = |idx: Option<usize>| idx.and_then(|i| record.get(i)).filter(|x| !x.is_empty());
+
+		if get(latest_idx) == Some("false") || get(deleted_idx) == Some("true") {
+			continue;
+		}
+
+		let key = get(Some(key_idx)).ok_or(S3ParseError::MissingField("Key"))?;
+		let meta = ObjectMeta {
+			size: get(size_idx).map(|x| parse_size("Size", x)).transpose()?,
+			last_modified: get(time_idx)
+				.map(|x| parse_time("LastModifiedDate", x))
+				.transpose()?,
+			etag: get(etag_idx).map(parse_etag),
+		};
+
+		out.push((url_decode("Key", key)?, meta));
+	}
+
+	return Ok(out);
+}
+
+//
+// MARK: index
+//
+
+impl DatapathIndex {
+	/// Build an index from the pages of a `ListObjectsV2` listing,
+	/// each given as a reader over that page's xml response.
+	pub fn from_list_objects_v2<R: BufRead, I: IntoIterator<Item = R>>(
+		pages: I,
+	) -> Result<Self, S3ParseError> {
+		let mut objects = Vec::new();
+		for page in pages {
+			objects.extend(ListObjectsV2Page::from_reader(page)?.contents);
+		}
+
+		Ok(Self::new_with_meta(objects.into_iter()))
+	}
+
+	/// Like [Self::from_list_objects_v2], but reads each page from a file
+	pub fn from_list_objects_v2_files<P: AsRef<Path>, I: IntoIterator<Item = P>>(
+		pages: I,
+	) -> Result<Self, S3ParseError> {
+		let pages = pages
+			.into_iter()
+			.map(|p| File::open(p).map(BufReader::new))
+			.collect::<Result<Vec<_>, _>>()?;
+
+		Self::from_list_objects_v2(pages)
+	}
+
+	/// Build an index from s3 inventory csv files.
+	/// See [read_inventory_csv].
+	pub fn from_inventory_csv<R: Read, I: IntoIterator<Item = R>>(
+		files: I,
+		file_schema: &str,
+	) -> Result<Self, S3ParseError> {
+		let mut objects = Vec::new();
+		for file in files {
+			objects.extend(read_inventory_csv(file, file_schema)?);
+		}
+
+		Ok(Self::new_with_meta(objects.into_iter()))
+	}
+
+	/// Build an index from an s3 inventory `manifest.json`.
+	///
+	/// `root` is a local copy of the inventory's destination bucket:
+	/// each data file is read from `root/<key>`, and decompressed if
+	/// its key ends with `.gz`. Only `CSV` inventories are supported.
+	///
+	/// Keys that could point outside of `root`, like `../x` or `/x`, are rejected.
+	pub fn from_inventory_manifest(
+		manifest: impl AsRef<Path>,
+		root: impl AsRef<Path>,
+	) -> Result<Self, S3ParseError> {
+		let manifest = InventoryManifest::from_reader(BufReader::new(File::open(manifest)?))?;
+
+		if manifest.file_format != "CSV" {
+			return Err(S3ParseError::InvalidField {
+				field: "fileFormat",
+				value: manifest.file_format,
+			});
+		}
+
+		let mut objects = Vec::new();
+		for file in &manifest.files {
+			let relative = Path::new(&file.key)
+				.components()
+				.all(|x| matches!(x, Component::Normal(_)));
+			if !relative {
+				return Err(S3ParseError::InvalidField {
+					field: "key",
+					value: file.key.clone(),
+				});
+			}
+
+			let reader = BufReader::new(File::open(root.as_ref().join(&file.key))?);
+			if file.key.ends_with(".gz") {
+				objects.extend(read_inventory_csv(
+					GzDecoder::new(reader),
+					&manifest.file_schema,
+				)?);
+			} else {
+				objects.extend(read_inventory_csv(reader, &manifest.file_schema)?);
+			}
+		}
+
+		Ok(Self::new_with_meta(objects.into_iter()))
+	}
+}
+
+//
+// MARK: tests
+//
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod s3_tests {
+	use super::*;
+	use flate2::{Compression, write::GzEncoder};
+	use std::io::Write;
+
+	const PAGE_1: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+	<Name>bucket</Name>
+	<Prefix>web/</Prefix>
+	<KeyCount>2</KeyCount>
+	<MaxKeys>2</MaxKeys>
+	<IsTruncated>true</IsTruncated>
+	<NextContinuationToken>token-1</NextContinuationToken>
+	<Contents>
+		<Key>web/domain=example.com/ts=1234/a.json</Key>
+		<LastModified>2024-01-02T03:04:05.000Z</LastModified>
+		<ETag>&quot;abc123&quot;</ETag>
+		<Size>42</Size>
+		<StorageClass>STANDARD</StorageClass>
+	</Contents>
+	<Contents>
+		<Key>web/domain=a&amp;b.com/ts=1234/b.json</Key>
+		<LastModified>2024-01-02T03:04:05.000Z</LastModified>
+		<ETag>"def456"</ETag>
+		<Size>7</Size>
+	</Contents>
+</ListBucketResult>"#;
+
+	const PAGE_2: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+	<Name>bucket</Name>
+	<Prefix>web%2F</Prefix>
+	<EncodingType>url</EncodingType>
+	<IsTruncated>false</IsTruncated>
+	<ContinuationToken>token-1</ContinuationToken>
+	<Contents>
+		<Key>web/domain%3Dother.com/ts%3D5678/my+file%25.json</Key>
+		<LastModified>2024-01-03T00:00:00.000Z</LastModified>
+		<ETag>"0123"</ETag>
+		<Size>1</Size>
+	</Contents>
+	<CommonPrefixes>
+		<Prefix>web%2Fdomain%3Dx%2F</Prefix>
+	</CommonPrefixes>
+</ListBucketResult>"#;
+
+	#[test]
+	fn parse_list_objects_page() {
+		let page = ListObjectsV2Page::from_reader(PAGE_1.as_bytes()).unwrap();
+		assert_eq!(page.name.as_deref(), Some("bucket"));
+		assert_eq!(page.prefix.as_deref(), Some("web/"));
+		assert!(page.is_truncated);
+		assert_eq!(page.next_continuation_token.as_deref(), Some("token-1"));
+		assert_eq!(page.contents.len(), 2);
+
+		let (key, meta) = &page.contents[0];
+		assert_eq!(key, "web/domain=example.com/ts=1234/a.json");
+		assert_eq!(meta.size, Some(42));
+		assert_eq!(meta.etag.as_deref(), Some("abc123"));
+		assert_eq!(
+			meta.last_modified,
+			Some(parse_time("", "2024-01-02T03:04:05Z").unwrap())
+		);
+
+		assert_eq!(page.contents[1].0, "web/domain=a&b.com/ts=1234/b.json");
+	}
+
+	#[test]
+	fn parse_url_encoded_page() {
+		let page = ListObjectsV2Page::from_reader(PAGE_2.as_bytes()).unwrap();
+		assert_eq!(page.prefix.as_deref(), Some("web/"));
+		assert!(!page.is_truncated);
+		assert_eq!(page.continuation_token.as_deref(), Some("token-1"));
+		assert_eq!(
+			page.contents[0].0,
+			"web/domain=other.com/ts=5678/my file%.json"
+		);
+		assert_eq!(page.common_prefixes, vec!["web/domain=x/".to_owned()]);
+	}
+
+	#[test]
+	fn index_from_list_objects() {
+		let idx =
+			DatapathIndex::from_list_objects_v2([PAGE_1.as_bytes(), PAGE_2.as_bytes()]).unwrap();
+		assert_eq!(idx.len(), 3);
+		assert_eq!(idx.query("web/domain=*/ts=*/*").unwrap().count(), 3);
+
+		let meta = idx
+			.get_meta("web/domain=other.com/ts=5678/my file%.json")
+			.unwrap();
+		assert_eq!(meta.size, Some(1));
+		assert_eq!(meta.etag.as_deref(), Some("0123"));
+	}
+
+	#[test]
+	fn missing_key() {
+		let xml = "<ListBucketResult><Contents><Size>1</Size></Contents></ListBucketResult>";
+		assert!(matches!(
+			ListObjectsV2Page::from_reader(xml.as_bytes()),
+			Err(S3ParseError::MissingField("Key"))
+		));
+	}
+
+	const SCHEMA: &str =
+		"Bucket, Key, VersionId, IsLatest, IsDeleteMarker, Size, LastModifiedDate, ETag";
+	const CSV: &str = "\
+\"bucket\",\"web/domain%3Dexample.com/ts%3D1234/a.json\",\"v1\",\"true\",\"false\",\"42\",\"2024-01-02T03:04:05.000Z\",\"abc123\"
+\"bucket\",\"web/domain%3Dexample.com/ts%3D1234/a.json\",\"v0\",\"false\",\"false\",\"40\",\"2024-01-01T03:04:05.000Z\",\"aaa\"
+\"bucket\",\"web/domain%3Dexample.com/ts%3D1234/gone.json\",\"v2\",\"true\",\"true\",\"\",\"2024-01-02T03:04:05.000Z\",\"\"
+\"bucket\",\"web/domain%3Dother.com/ts%3D5678/b+c.json\",\"v3\",\"true\",\"false\",\"7\",\"2024-01-02T03:04:05.000Z\",\"def456\"
+";
+
+	#[test]
+	fn parse_inventory_csv() {
+		let objects = read_inventory_csv(CSV.as_bytes(), SCHEMA).unwrap();
+		assert_eq!(objects.len(), 2);
+		assert_eq!(objects[0].0, "web/domain=example.com/ts=1234/a.json");
+		assert_eq!(objects[0].1.size, Some(42));
+		assert_eq!(objects[0].1.etag.as_deref(), Some("abc123"));
+		assert_eq!(objects[1].0, "web/domain=other.com/ts=5678/b c.json");
+	}
+
+	#[test]
+	fn index_from_inventory_manifest() {
+		let root = std::env::temp_dir().join(format!("datapath-{}", uuid::Uuid::new_v4()));
+		let data = root.join("inventory/bucket/config/data");
+		std::fs::create_dir_all(&data).unwrap();
+
+		let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+		gz.write_all(CSV.as_bytes()).unwrap();
+		std::fs::write(data.join("part-0.csv.gz"), gz.finish().unwrap()).unwrap();
+
+		let manifest = root.join("manifest.json");
+		std::fs::write(
+			&manifest,
+			format!(
+				r#"{{
+					"sourceBucket": "bucket",
+					"fileFormat": "CSV",
+					"fileSchema": "{SCHEMA}",
+					"files": [{{
+						"key": "inventory/bucket/config/data/part-0.csv.gz",
+						"size": 123,
+						"MD5checksum": "f00"
+					}}]
+				}}"#
+			),
+		)
+		.unwrap();
+
+		let idx = DatapathIndex::from_inventory_manifest(&manifest, &root).unwrap();
+
+		// Keys can't leave `root`
+		let text = std::fs::read_to_string(&manifest).unwrap();
+		for key in ["../data/part-0.csv.gz", "/etc/passwd"] {
+			let bad = text.replace("inventory/bucket/config/data/part-0.csv.gz", key);
+			std::fs::write(&manifest, bad).unwrap();
+			assert!(matches!(
+				DatapathIndex::from_inventory_manifest(&manifest, &root),
+				Err(S3ParseError::InvalidField { field: "key", .. })
+			));
+		}
+
+		std::fs::remove_dir_all(&root).unwrap();
+
+		assert_eq!(idx.len(), 2);
+		assert_eq!(
+			idx.get_meta("web/domain=example.com/ts=1234/a.json")
+				.unwrap()
+				.size,
+			Some(42)
+		);
+		assert!(
+			idx.get_meta("web/domain=example.com/ts=1234/gone.json")
+				.is_none()
+		);
+	}
+}
@@ -0,0 +1,460 @@
+use serde_json::Value;
+use std::{
+	cmp::Ordering,
+	collections::{HashMap, VecDeque},
+	io::Read,
+};
+
+use super::{S3ParseError, parse_etag, parse_time, url_decode};
+use crate::{DatapathIndex, ObjectMeta};
+
+//
+// MARK: events
+//
+
+/// What an [S3Event] did to its object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum S3EventKind {
+	/// An `ObjectCreated:*` event
+	Created,
+
+	/// An `ObjectRemoved:*` event, including `DeleteMarkerCreated`
+	Removed,
+}
+
+/// One s3 event notification about an object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Event {
+	pub kind: S3EventKind,
+
+	/// The bucket this event happened in
+	pub bucket: Option<String>,
+
+	/// The decoded key of the object this event is about
+	pub key: String,
+
+	/// The metadata of a created object.
+	/// `last_modified` is the time of this event.
+	pub meta: ObjectMeta,
+
+	/// This event's `sequencer`, used to order events on the same key
+	pub sequencer: Option<String>,
+}
+
+/// Read s3 event notifications from a json document.
+///
+/// This accepts:
+/// - s3 notifications (`{"Records": [...]}`), as delivered to sqs or lambda.
+///   Keys in these are url-encoded, and are decoded here.
+/// - EventBridge events with `"source": "aws.s3"`
+/// - sns notifications and sqs messages that wrap either of the above
+///   in their `Message` or `Body` field
+/// - arrays of any of these
+///
+/// Events other than `ObjectCreated:*` and `ObjectRemoved:*`
+/// (like `s3:TestEvent` or restore events) are skipped.
+pub fn read_s3_events<R: Read>(reader: R) -> Result<Vec<S3Event>, S3ParseError> {
+	let json: Value = serde_json::from_reader(reader)?;
+	let mut out = Vec::new();
+	collect_events(&json, &mut out)?;
+	return Ok(out);
+}
+
+fn collect_events(json: &Value, out: &mut Vec<S3Event>) -> Result<(), S3ParseError> {
+	match json {
+		Value::Array(items) => {
+			for item in items {
+				collect_events(item, out)?;
+			}
+		}
+
+		// An sns or sqs envelope around an event document
+		Value::Object(obj)
+			if ["Message", "Body", "body"]
+				.iter()
+				.any(|k| obj.contains_key(*k)) =>
+		{
+			let inner = ["Message", "Body", "body"]
+				.iter()
+				.find_map(|k| obj.get(*k))
+				.and_then(|x| x.as_str())
+				.ok_or(S3ParseError::MissingField("Message"))?;
+			collect_events(&serde_json::from_str(inner)?, out)?;
+		}
+
+		Value::Object(obj) if obj.contains_key("Records") => {
+			let records = obj
+				.get("Records")
+				.and_then(|x| x.as_array())
+				.ok_or(S3ParseError::MissingField("Records"))?;
+
+			for record in records {
+				out.extend(parse_record(record)?);
+			}
+		}
+
+		Value::Object(obj) if obj.get("source").and_then(|x| x.as_str()) == Some("aws.s3") => {
+			out.extend(parse_eventbridge(json)?);
+		}
+
+		// Test events and anything else we don't know
+		_ => {}
+	}
+
+	Ok(())
+}
+
+fn str_at<'a>(json: &'a Value, path: &[&str]) -> Option<&'a str> {
+	path.iter()
+		.try_fold(json, |x, k| x.get(*k))
+		.and_then(|x| x.as_str())
+}
+
+/// Parse one record of an s3 notification
+fn parse_record(record: &Value) -> Result<Option<S3Event>, S3ParseError> {
+	let name = str_at(record, &["eventName"]).ok_or(S3ParseError::MissingField("eventName"))?;
+	let kind = if name.starts_with("ObjectCreated:") {
+		S3EventKind::Created
+	} else if name.starts_with("ObjectRemoved:") {
+		S3EventKind::Removed
+	} else {
+		return Ok(None);
+	};
+
+	let object = record
+		.get("s3")
+		.and_then(|x| x.get("object"))
+		.ok_or(S3ParseError::MissingField("object"))?;
+	let key = str_at(object, &["key"]).ok_or(S3ParseError::MissingField("key"))?;
+
+	Ok(Some(S3Event {
+		kind,
+		bucket: str_at(record, &["s3", "bucket", "name"]).map(|x| x.to_owned()),
+		key: url_decode("key", key)?,
+		meta: parse_meta(kind, object, "eTag", str_at(record, &["eventTime"]))?,
+		sequencer: str_at(object, &["sequencer"]).map(|x| x.to_owned()),
+	}))
+}
+
+/// Parse an EventBridge event from s3
+fn parse_eventbridge(event: &Value) -> Result<Option<S3Event>, S3ParseError> {
+	let kind = match str_at(event, &["detail-type"]) {
+		Some("Object Created") => S3EventKind::Created,
+		Some("Object Deleted") => S3EventKind::Removed,
+		_ => return Ok(None),
+	};
+
+	let object = event
+		.get("detail")
+		.and_then(|x| x.get("object"))
+		.ok_or(S3ParseError::MissingField("object"))?;
+	let key = str_at(object, &["key"]).ok_or(S3ParseError::MissingField("key"))?;
+
+	Ok(Some(S3Event {
+		kind,
+		bucket: str_at(event, &["detail", "bucket", "name"]).map(|x| x.to_owned()),
+		key: key.to_owned(),
+		meta: parse_meta(kind, object, "etag", str_at(event, &["time"]))?,
+		sequencer: str_at(object, &["sequencer"]).map(|x| x.to_owned()),
+	}))
+}
+
+fn parse_meta(
+	kind: S3EventKind,
+	object: &Value,
+	etag_field: &str,
+	time: Option<&str>,
+) -> Result<ObjectMeta, S3ParseError> {
+	if kind == S3EventKind::Removed {
+		return Ok(ObjectMeta::default());
+	}
+
+	Ok(ObjectMeta {
+		size: object.get("size").and_then(|x| x.as_u64()),
+		last_modified: time.map(|x| parse_time("eventTime", x)).transpose()?,
+		etag: str_at(object, &[etag_field]).map(parse_etag),
+	})
+}
+
+//
+// MARK: applier
+//
+
+/// Compare two s3 event sequencers.
+///
+/// Sequencers are hex strings. Per the s3 docs, the
+/// shorter one is padded with zeros before comparing.
+fn compare_sequencers(a: &str, b: &str) -> Ordering {
+	let len = a.len().max(b.len());
+	let a = format!("{:0<len$}", a.to_ascii_uppercase());
+	let b = format!("{:0<len$}", b.to_ascii_uppercase());
+	a.cmp(&b)
+}
+
+/// Applies [S3Event]s to a [DatapathIndex], so that it tracks a bucket
+/// without re-listing it.
+///
+/// This remembers the last sequencer it applied for each key, so events
+/// that are delivered twice or out of order are ignored. Use one applier
+/// per bucket, since sequencers are only comparable on the same key.
+///
+/// To keep memory bounded, a key's sequencer is forgotten once
+/// [a window](Self::with_window) of later events has been applied,
+/// and none of them were about that key. A duplicate or stale event
+/// delivered after that is applied again.
+#[derive(Debug, Clone)]
+pub struct S3EventApplier {
+	/// The last sequencer we applied on each key,
+	/// and the number of the event it came from
+	sequencers: HashMap<String, (String, u64)>,
+
+	/// The key and number of the last `window` events with a sequencer
+	recent: VecDeque<(String, u64)>,
+
+	/// The number of events with a sequencer we have applied
+	applied: u64,
+
+	/// The number of events we remember sequencers for
+	window: usize,
+}
+
+impl Default for S3EventApplier {
+	fn default() -> Self {
+		Self {
+			sequencers: HashMap::new(),
+			recent: VecDeque::new(),
+			applied: 0,
+			window: 100_000,
+		}
+	}
+}
+
+impl S3EventApplier {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Remember the sequencers of the last `events` events that had one.
+	/// The default is 100 000. This should be much larger than the number
+	/// of events that may be delivered late or twice.
+	pub fn with_window(mut self, events: usize) -> Self {
+		self.window = events.max(1);
+		self
+	}
+
+	/// Apply one event to `index`.
+	///
+	/// Returns `false` if this event was ignored because
+	/// we already applied this or a later event on its key.
+	pub fn apply(&mut self, index: &mut DatapathIndex, event: &S3Event) -> bool {
+		if let Some(seq) = &event.sequencer {
+			let n = self.applied;
+			match self.sequencers.get_mut(&event.key) {
+				Some((last, _)) if compare_sequencers(seq, last).is_le() => return false,
+				Some(last) => *last = (seq.clone(), n),
+				None => {
+					self.sequencers.insert(event.key.clone(), (seq.clone(), n));
+				}
+			}
+
+			self.applied += 1;
+			self.recent.push_back((event.key.clone(), n));
+			while self.recent.len() > self.window {
+				let Some((key, n)) = self.recent.pop_front() else {
+					break;
+				};
+
+				// Keep keys that had a later event
+				if self.sequencers.get(&key).is_some_and(|(_, x)| *x == n) {
+					self.sequencers.remove(&key);
+				}
+			}
+		}
+
+		match event.kind {
+			S3EventKind::Created => index.insert(&event.key, event.meta.clone()),
+			S3EventKind::Removed => index.remove(&event.key),
+		};
+
+		return true;
+	}
+
+	/// Apply many events to `index`, in order.
+	/// Returns the number of events that were not ignored.
+	pub fn apply_all(&mut self, index: &mut DatapathIndex, events: &[S3Event]) -> usize {
+		events.iter().filter(|e| self.apply(index, e)).count()
+	}
+}
+
+//
+// MARK: tests
+//
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod events_tests {
+	use super::*;
+
+	const NOTIFICATION: &str = r#"{
+		"Records": [
+			{
+				"eventVersion": "2.1",
+				"eventSource": "aws:s3",
+				"eventTime": "2024-01-02T03:04:05.000Z",
+				"eventName": "ObjectCreated:Put",
+				"s3": {
+					"bucket": { "name": "bucket" },
+					"object": {
+						"key": "web/domain%3Dexample.com/ts%3D1234/my+file.json",
+						"size": 42,
+						"eTag": "abc123",
+						"sequencer": "0055AED6DCD90281E5"
+					}
+				}
+			},
+			{
+				"eventVersion": "2.1",
+				"eventSource": "aws:s3",
+				"eventTime": "2024-01-02T03:04:06.000Z",
+				"eventName": "ObjectRemoved:Delete",
+				"s3": {
+					"bucket": { "name": "bucket" },
+					"object": {
+						"key": "web/old.json",
+						"sequencer": "0055AED6DCD90281E6"
+					}
+				}
+			},
+			{
+				"eventVersion": "2.1",
+				"eventSource": "aws:s3",
+				"eventTime": "2024-01-02T03:04:06.000Z",
+				"eventName": "ObjectRestore:Completed",
+				"s3": {
+					"bucket": { "name": "bucket" },
+					"object": { "key": "web/cold.json" }
+				}
+			}
+		]
+	}"#;
+
+	#[test]
+	fn parse_notification() {
+		let events = read_s3_events(NOTIFICATION.as_bytes()).unwrap();
+		assert_eq!(events.len(), 2);
+
+		assert_eq!(events[0].kind, S3EventKind::Created);
+		assert_eq!(events[0].bucket.as_deref(), Some("bucket"));
+		assert_eq!(events[0].key, "web/domain=example.com/ts=1234/my file.json");
+		assert_eq!(events[0].meta.size, Some(42));
+		assert_eq!(events[0].meta.etag.as_deref(), Some("abc123"));
+		assert!(events[0].meta.last_modified.is_some());
+
+		assert_eq!(events[1].kind, S3EventKind::Removed);
+		assert_eq!(events[1].key, "web/old.json");
+		assert!(events[1].meta.is_empty());
+	}
+
+	#[test]
+	fn parse_envelopes() {
+		// An sqs message holding an sns notification holding an s3 notification
+		let sns = serde_json::json!({ "Type": "Notification", "Message": NOTIFICATION });
+		let sqs = serde_json::json!([{ "MessageId": "1", "Body": sns.to_string() }]);
+		let events = read_s3_events(sqs.to_string().as_bytes()).unwrap();
+		assert_eq!(events.len(), 2);
+
+		let test = r#"{ "Service": "Amazon S3", "Event": "s3:TestEvent", "Bucket": "bucket" }"#;
+		assert!(read_s3_events(test.as_bytes()).unwrap().is_empty());
+
+		let eventbridge = r#"{
+			"source": "aws.s3",
+			"detail-type": "Object Created",
+			"time": "2024-01-02T03:04:05Z",
+			"detail": {
+				"bucket": { "name": "bucket" },
+				"object": { "key": "web/a b.json", "size": 7, "etag": "e1", "sequencer": "01" }
+			}
+		}"#;
+		let events = read_s3_events(eventbridge.as_bytes()).unwrap();
+		assert_eq!(events.len(), 1);
+		assert_eq!(events[0].key, "web/a b.json");
+		assert_eq!(events[0].meta.size, Some(7));
+
+		assert!(read_s3_events(r#"{ "Records": [{}] }"#.as_bytes()).is_err());
+	}
+
+	#[test]
+	fn sequencers() {
+		assert!(compare_sequencers("0055AED6DCD90281E5", "0055AED6DCD90281E6").is_lt());
+		assert!(compare_sequencers("0055AED6DCD90281E5", "0055aed6dcd90281e5").is_eq());
+		assert!(compare_sequencers("0055AED6DCD90281E5", "0055AED6DCD90281E500").is_eq());
+		assert!(compare_sequencers("0055AED6DCD90281E5", "0055AED6DCD90281E501").is_lt());
+	}
+
+	#[test]
+	fn apply_events() {
+		let event = |kind, key: &str, seq: &str| S3Event {
+			kind,
+			bucket: None,
+			key: key.to_owned(),
+			meta: ObjectMeta::default(),
+			sequencer: Some(seq.to_owned()),
+		};
+
+		let mut index = DatapathIndex::new(["web/old.json"].into_iter());
+		let mut applier = S3EventApplier::new();
+
+		let events = read_s3_events(NOTIFICATION.as_bytes()).unwrap();
+		assert_eq!(applier.apply_all(&mut index, &events), 2);
+		assert_eq!(
+			index.query("**").unwrap().collect::<Vec<_>>(),
+			vec!["web/domain=example.com/ts=1234/my file.json"]
+		);
+		assert_eq!(
+			index
+				.get_meta("web/domain=example.com/ts=1234/my file.json")
+				.unwrap()
+				.size,
+			Some(42)
+		);
+
+		// Duplicates are ignored
+		assert_eq!(applier.apply_all(&mut index, &events), 0);
+
+		// So are events older than the last one we applied
+		let key = "web/a.json";
+		assert!(applier.apply(&mut index, &event(S3EventKind::Created, key, "02")));
+		assert!(applier.apply(&mut index, &event(S3EventKind::Removed, key, "04")));
+		assert!(!applier.apply(&mut index, &event(S3EventKind::Created, key, "03")));
+		assert!(index.get_meta(key).is_none());
+		assert!(applier.apply(&mut index, &event(S3EventKind::Created, key, "05")));
+		assert!(index.get_meta(key).is_some());
+		assert_eq!(index.len(), 2);
+	}
+
+	#[test]
+	fn apply_window() {
+		let event = |key: &str, seq: &str| S3Event {
+			kind: S3EventKind::Created,
+			bucket: None,
+			key: key.to_owned(),
+			meta: ObjectMeta::default(),
+			sequencer: Some(seq.to_owned()),
+		};
+
+		let mut index = DatapathIndex::new_empty();
+		let mut applier = S3EventApplier::new().with_window(2);
+
+		assert!(applier.apply(&mut index, &event("a", "01")));
+		assert!(applier.apply(&mut index, &event("b", "01")));
+		assert!(applier.apply(&mut index, &event("a", "02")));
+		assert!(!applier.apply(&mut index, &event("a", "01")));
+
+		// `b` was forgotten after two later events, but `a` wasn't
+		assert!(applier.apply(&mut index, &event("c", "01")));
+		assert_eq!(applier.sequencers.len(), 2);
+		assert!(applier.apply(&mut index, &event("b", "01")));
+		assert!(!applier.apply(&mut index, &event("c", "01")));
+		assert!(applier.recent.len() <= 2);
+	}
+}
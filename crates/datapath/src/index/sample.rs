@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use tracing::trace;
+
+use crate::{DatapathIndex, Rule, index::query::Matches};
+
+/// A small, seeded random number generator (splitmix64).
+/// Samples only need to be reproducible, not secure.
+struct SampleRng(u64);
+
+impl SampleRng {
+	fn next(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		z ^ (z >> 31)
+	}
+
+	/// A random number in `0..n`
+	fn below(&mut self, n: usize) -> usize {
+		((self.next() as u128 * n as u128) >> 64) as usize
+	}
+}
+
+/// A reservoir of at most `size` paths, tagged with their position in the results
+#[derive(Default)]
+struct Reservoir {
+	size: usize,
+
+	/// The number of paths offered so far
+	seen: usize,
+	paths: Vec<(usize, String)>,
+}
+
+impl Reservoir {
+	/// Offer the `i`th result, `path`, to this reservoir.
+	/// `path` is only called if it is kept.
+	fn offer(&mut self, rng: &mut SampleRng, i: usize, path: impl FnOnce() -> String) {
+		self.seen += 1;
+		if self.paths.len() < self.size {
+			self.paths.push((i, path()));
+			return;
+		}
+
+		let j = rng.below(self.seen);
+		if let Some(x) = self.paths.get_mut(j) {
+			*x = (i, path());
+		}
+	}
+}
+
+impl DatapathIndex {
+	/// Pick `n` random paths that match `query`.
+	///
+	/// Every matching path is equally likely to be picked, and the same
+	/// seed always picks the same paths from the same index. This visits
+	/// every match once, without collecting them. The sample is returned
+	/// in order, and holds every match if there are fewer than `n`.
+	///
+	/// Returns `None` if the query was invalid.
+	pub fn sample(&self, query: impl Into<String>, n: usize, seed: u64) -> Option<Vec<String>> {
+		let rule = Rule::new(query)?;
+		Some(self.sample_rule(&rule, n, seed))
+	}
+
+	/// Like [Self::sample], but with a precompiled rule
+	pub fn sample_rule(&self, rule: &Rule, n: usize, seed: u64) -> Vec<String> {
+		trace!("DatapathIndex query is {}", rule.pattern());
+
+		let mut rng = SampleRng(seed);
+		let mut reservoir = Reservoir {
+			size: n,
+			..Default::default()
+		};
+
+		let mut i = 0;
+		let mut matches = Matches::new(&self.root, self.matcher(rule));
+		while let Some((path, _)) = matches.next_ref() {
+			reservoir.offer(&mut rng, i, || path.to_string());
+			i += 1;
+		}
+
+		reservoir.paths.sort_by_key(|(i, _)| *i);
+		reservoir.paths.into_iter().map(|(_, path)| path).collect()
+	}
+
+	/// Like [Self::sample], but pick paths evenly across
+	/// the distinct values of the partition `key`.
+	///
+	/// Each value gets the same share of `n`. Values with fewer paths
+	/// than their share give the rest to the others. Paths without `key`
+	/// are never picked, and if `key` appears more than once in a path,
+	/// only its first value is used (like [Self::distinct_values]).
+	///
+	/// Returns `None` if the query was invalid.
+	pub fn sample_stratified(
+		&self,
+		query: impl Into<String>,
+		key: &str,
+		n: usize,
+		seed: u64,
+	) -> Option<Vec<String>> {
+		let rule = Rule::new(query)?;
+		Some(self.sample_stratified_rule(&rule, key, n, seed))
+	}
+
+	/// Like [Self::sample_stratified], but with a precompiled rule
+	pub fn sample_stratified_rule(
+		&self,
+		rule: &Rule,
+		key: &str,
+		n: usize,
+		seed: u64,
+	) -> Vec<String> {
+		let counts = self.distinct_counts(rule, key);
+
+		// Share `n` out one at a time, so that it is split as evenly as possible
+		let mut strata: BTreeMap<String, Reservoir> = BTreeMap::new();
+		let mut left = n;
+		while left > 0 {
+			let mut gave = false;
+			for (value, count) in &counts {
+				if left == 0 {
+					break;
+				}
+
+				let stratum = strata.entry(value.clone()).or_default();
+				if stratum.size < *count {
+					stratum.size += 1;
+					left -= 1;
+					gave = true;
+				}
+			}
+
+			// Every value is full
+			if !gave {
+				break;
+			}
+		}
+
+		let mut rng = SampleRng(seed);
+		let mut i = 0;
+		let mut matches = Matches::new(&self.root, self.matcher(rule));
+		while let Some((path, _)) = matches.next_ref() {
+			let value = path.segments().iter().find_map(|seg| {
+				seg.split_once('=')
+					.filter(|(k, _)| *k == key)
+					.map(|(_, v)| v)
+			});
+
+			if let Some(stratum) = value.and_then(|x| strata.get_mut(x)) {
+				stratum.offer(&mut rng, i, || path.to_string());
+			}
+			i += 1;
+		}
+
+		let mut paths = strata
+			.into_values()
+			.flat_map(|x| x.paths)
+			.collect::<Vec<_>>();
+		paths.sort_by_key(|(i, _)| *i);
+		paths.into_iter().map(|(_, path)| path).collect()
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod sample_tests {
+	use super::*;
+
+	fn index() -> DatapathIndex {
+		let mut paths = Vec::new();
+		for i in 0..90 {
+			paths.push(format!("capture/user_id=a/ts={i}/raw/{i}.json"));
+		}
+		for i in 0..9 {
+			paths.push(format!("capture/user_id=b/ts={i}/raw/{i}.json"));
+		}
+		paths.push("capture/user_id=c/ts=0/raw/0.json".to_owned());
+		paths.push("capture/user_id=c/ts=0/raw/0.txt".to_owned());
+		DatapathIndex::new(paths.into_iter())
+	}
+
+	#[test]
+	fn sample() {
+		let idx = index();
+
+		let a = idx.sample("capture/**/raw/*.json", 10, 1).unwrap();
+		assert_eq!(a.len(), 10);
+		assert!(a.iter().all(|x| x.ends_with(".json")));
+		assert_eq!(a, idx.sample("capture/**/raw/*.json", 10, 1).unwrap());
+		assert_ne!(a, idx.sample("capture/**/raw/*.json", 10, 2).unwrap());
+
+		// Samples are in order
+		let mut sorted = a.clone();
+		sorted.sort_by(|x, y| x.split('/').cmp(y.split('/')));
+		assert_eq!(a, sorted);
+
+		// Small results are returned whole
+		let all = idx.sample("capture/user_id=c/**", 10, 1).unwrap();
+		assert_eq!(all.len(), 2);
+		assert!(idx.sample("capture/**", 0, 1).unwrap().is_empty());
+
+		assert!(idx.sample("capture/***", 10, 1).is_none());
+	}
+
+	#[test]
+	fn sample_is_uniform() {
+		let idx = index();
+
+		// Every path should be picked about as often
+		let mut picked = BTreeMap::new();
+		for seed in 0..1000 {
+			for path in idx.sample("capture/user_id=b/**", 3, seed).unwrap() {
+				*picked.entry(path).or_insert(0) += 1;
+			}
+		}
+
+		assert_eq!(picked.len(), 9);
+		assert!(picked.values().all(|x| (250..420).contains(x)));
+	}
+
+	#[test]
+	fn sample_stratified() {
+		let idx = index();
+
+		let count = |sample: &[String], user: &str| {
+			sample
+				.iter()
+				.filter(|x| x.contains(&format!("user_id={user}/")))
+				.count()
+		};
+
+		let s = idx
+			.sample_stratified("capture/**", "user_id", 9, 7)
+			.unwrap();
+		assert_eq!(s.len(), 9);
+		assert_eq!(count(&s, "a"), 4);
+		assert_eq!(count(&s, "b"), 3);
+		assert_eq!(count(&s, "c"), 2);
+		assert_eq!(
+			s,
+			idx.sample_stratified("capture/**", "user_id", 9, 7)
+				.unwrap()
+		);
+
+		// Small strata give the rest of their share to the others
+		let s = idx
+			.sample_stratified("capture/**", "user_id", 30, 7)
+			.unwrap();
+		assert_eq!(count(&s, "a"), 19);
+		assert_eq!(count(&s, "b"), 9);
+		assert_eq!(count(&s, "c"), 2);
+
+		let s = idx
+			.sample_stratified("capture/**", "missing", 30, 7)
+			.unwrap();
+		assert!(s.is_empty());
+	}
+}
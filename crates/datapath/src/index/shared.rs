@@ -0,0 +1,183 @@
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::trace;
+
+use crate::{DatapathIndex, ObjectMeta};
+
+/// One change to apply to a [SharedIndex], see [SharedIndex::apply]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexUpdate {
+	/// Add a path, or replace its metadata if it already exists
+	Insert(String, ObjectMeta),
+
+	/// Remove a path
+	Remove(String),
+}
+
+/// A [DatapathIndex] that many threads can read while another updates it.
+///
+/// Readers take a [snapshot](Self::snapshot), which is an immutable
+/// version of the index that they may query for as long as they like.
+/// Updates build a new version and swap it in, so they never change
+/// a snapshot that is being read. Readers only wait for the swap itself,
+/// never for an update to be built.
+///
+/// Versions don't share any nodes, so every [update](Self::update)
+/// copies the whole index. This takes time and memory proportional to the
+/// size of the index, not the size of the change: adding one path to an
+/// index of a million paths copies a million paths. Two versions are alive
+/// while an update is built, and more if old snapshots are still held.
+///
+/// This suits indices that are read often and updated in large, infrequent
+/// batches. Collect small changes and [apply](Self::apply) them together,
+/// rather than updating once per object. If you rebuild the index anyway,
+/// [store](Self::store) it, which doesn't copy anything.
+///
+/// Share this with an [Arc].
+#[derive(Debug)]
+pub struct SharedIndex {
+	/// The current version
+	current: RwLock<Arc<DatapathIndex>>,
+
+	/// Held while an update is built, so concurrent updates aren't lost
+	writer: Mutex<()>,
+}
+
+impl SharedIndex {
+	pub fn new(index: DatapathIndex) -> Self {
+		Self {
+			current: RwLock::new(Arc::new(index)),
+			writer: Mutex::new(()),
+		}
+	}
+
+	/// Get the current version of this index.
+	///
+	/// Later updates don't change the returned index,
+	/// so queries on it always see the same paths.
+	///
+	/// This takes a read lock to clone an [Arc], so it is cheap but not free.
+	/// Take one snapshot for a group of related queries,
+	/// rather than one per query.
+	pub fn snapshot(&self) -> Arc<DatapathIndex> {
+		// Nothing panics while this lock is held, so it can't be poisoned
+		let current = self.current.read().unwrap_or_else(|x| x.into_inner());
+		current.clone()
+	}
+
+	/// Replace this index with `index`, like after a full refresh.
+	/// Returns the previous version.
+	pub fn store(&self, index: DatapathIndex) -> Arc<DatapathIndex> {
+		let _writer = self.writer.lock().unwrap_or_else(|x| x.into_inner());
+		self.swap(Arc::new(index))
+	}
+
+	/// Update a copy of the current version with `f`, then swap it in.
+	///
+	/// This copies the whole index on every call, however small `f` is,
+	/// so batch as many changes as possible into each call.
+	/// Updates never run at the same time: if two threads
+	/// update this index at once, both changes are kept.
+	pub fn update(&self, f: impl FnOnce(&mut DatapathIndex)) {
+		let _writer = self.writer.lock().unwrap_or_else(|x| x.into_inner());
+
+		let mut next = DatapathIndex::clone(&self.snapshot());
+		f(&mut next);
+		self.swap(Arc::new(next));
+	}
+
+	/// Apply a batch of updates at once, in order.
+	/// Readers see either none or all of them.
+	///
+	/// The index is copied once per batch, not once per update.
+	pub fn apply(&self, updates: impl IntoIterator<Item = IndexUpdate>) {
+		self.update(|index| {
+			let mut n = 0usize;
+			for update in updates {
+				match update {
+					IndexUpdate::Insert(path, meta) => index.insert(&path, meta),
+					IndexUpdate::Remove(path) => index.remove(&path),
+				};
+				n += 1;
+			}
+			trace!("Applied {n} updates to SharedIndex");
+		});
+	}
+
+	/// Swap in a new version, returning the old one.
+	/// The caller must hold `self.writer`.
+	fn swap(&self, next: Arc<DatapathIndex>) -> Arc<DatapathIndex> {
+		let mut current = self.current.write().unwrap_or_else(|x| x.into_inner());
+		std::mem::replace(&mut current, next)
+	}
+}
+
+impl From<DatapathIndex> for SharedIndex {
+	fn from(index: DatapathIndex) -> Self {
+		Self::new(index)
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod shared_tests {
+	use super::*;
+
+	#[test]
+	fn snapshots() {
+		let shared = SharedIndex::new(DatapathIndex::new(
+			["web/ts=1/a.json", "web/ts=2/a.json"].into_iter(),
+		));
+
+		let before = shared.snapshot();
+		let mut iter = before.query("web/**").unwrap();
+		assert_eq!(iter.next().unwrap(), "web/ts=1/a.json");
+
+		shared.apply([
+			IndexUpdate::Remove("web/ts=2/a.json".to_owned()),
+			IndexUpdate::Insert("web/ts=3/a.json".to_owned(), ObjectMeta::default()),
+		]);
+
+		// Iterators keep reading the snapshot they started from
+		assert_eq!(iter.next().unwrap(), "web/ts=2/a.json");
+		assert!(iter.next().is_none());
+
+		let after = shared.snapshot();
+		assert_eq!(
+			after.query("web/**").unwrap().collect::<Vec<_>>(),
+			vec!["web/ts=1/a.json", "web/ts=3/a.json"]
+		);
+
+		let old = shared.store(DatapathIndex::new_empty());
+		assert_eq!(old.len(), 2);
+		assert!(shared.snapshot().is_empty());
+		assert_eq!(before.len(), 2);
+	}
+
+	#[test]
+	fn concurrent_updates() {
+		let shared = Arc::new(SharedIndex::from(DatapathIndex::new_empty()));
+
+		let handles = (0..4)
+			.map(|t| {
+				let shared = shared.clone();
+				std::thread::spawn(move || {
+					for i in 0..25 {
+						shared.update(|x| {
+							x.insert(&format!("web/t={t}/{i}.json"), ObjectMeta::default());
+						});
+
+						// Every snapshot is a complete version
+						let snapshot = shared.snapshot();
+						assert_eq!(snapshot.query_count("web/**").unwrap(), snapshot.len());
+					}
+				})
+			})
+			.collect::<Vec<_>>();
+
+		for handle in handles {
+			handle.join().unwrap();
+		}
+
+		assert_eq!(shared.snapshot().len(), 100);
+	}
+}
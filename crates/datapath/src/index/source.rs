@@ -0,0 +1,506 @@
+use futures::StreamExt;
+use std::{
+	collections::HashMap,
+	future::Future,
+	io::ErrorKind,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+};
+
+use crate::{DatapathIndex, ObjectMeta};
+
+//
+// MARK: source
+//
+
+/// One page of a listing
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListingPage {
+	/// The objects on this page
+	pub objects: Vec<(String, ObjectMeta)>,
+
+	/// The token to request the next page with.
+	/// `None` if this is the last page.
+	pub next_token: Option<String>,
+}
+
+/// Something that can list the objects in a bucket, one page at a time.
+///
+/// [DatapathIndex::from_source] drives the pagination,
+/// so implementations only need to fetch one page.
+/// Pages are fetched with futures that are [Send],
+/// so listings can run on a multithreaded runtime.
+pub trait ListingSource {
+	type Error;
+
+	/// List one page of the objects with keys starting with `prefix`.
+	///
+	/// `token` is `None` for the first page, and the
+	/// previous page's [ListingPage::next_token] after that.
+	fn list_page(
+		&self,
+		prefix: &str,
+		token: Option<&str>,
+	) -> impl Future<Output = Result<ListingPage, Self::Error>> + Send;
+}
+
+/// Take one page from `objects`, which are sorted by key,
+/// starting after the key `token`.
+/// The next token is the last key on this page.
+fn paginate(
+	objects: &[(String, ObjectMeta)],
+	token: Option<&str>,
+	page_size: usize,
+) -> ListingPage {
+	let start = match token {
+		None => 0,
+		Some(token) => objects.partition_point(|(k, _)| k.as_str() <= token),
+	};
+
+	let rest = &objects[start..];
+	let page = &rest[..rest.len().min(page_size.max(1))];
+
+	ListingPage {
+		next_token: page
+			.last()
+			.filter(|_| page.len() < rest.len())
+			.map(|(k, _)| k.clone()),
+		objects: page.to_vec(),
+	}
+}
+
+//
+// MARK: vec
+//
+
+/// A [ListingSource] over objects in memory
+#[derive(Debug, Clone)]
+pub struct VecSource {
+	/// Sorted by key
+	objects: Vec<(String, ObjectMeta)>,
+	page_size: usize,
+}
+
+impl VecSource {
+	pub fn new<S: Into<String>>(objects: impl IntoIterator<Item = (S, ObjectMeta)>) -> Self {
+		let mut objects = objects
+			.into_iter()
+			.map(|(k, v)| (k.into(), v))
+			.collect::<Vec<_>>();
+		objects.sort_by(|a, b| a.0.cmp(&b.0));
+
+		Self {
+			objects,
+			page_size: 1000,
+		}
+	}
+
+	/// Return at most `page_size` objects per page. The default is 1000.
+	pub fn with_page_size(mut self, page_size: usize) -> Self {
+		self.page_size = page_size;
+		self
+	}
+}
+
+impl ListingSource for VecSource {
+	type Error = std::convert::Infallible;
+
+	async fn list_page(
+		&self,
+		prefix: &str,
+		token: Option<&str>,
+	) -> Result<ListingPage, Self::Error> {
+		// Keys with this prefix are adjacent
+		let start = self.objects.partition_point(|(k, _)| k.as_str() < prefix);
+		let len = self.objects[start..].partition_point(|(k, _)| k.starts_with(prefix));
+
+		Ok(paginate(
+			&self.objects[start..start + len],
+			token,
+			self.page_size,
+		))
+	}
+}
+
+//
+// MARK: directory
+//
+
+/// A [ListingSource] over the files in a local directory.
+///
+/// Each file's key is its path relative to the root, separated by `/`.
+///
+/// The directory is walked once per listing, when its first page is requested,
+/// on tokio's blocking thread pool. Later pages are read from a sorted copy of
+/// that walk, which is dropped once its last page is returned. This needs a tokio runtime.
+#[derive(Debug, Clone)]
+pub struct DirectorySource {
+	root: PathBuf,
+	page_size: usize,
+
+	/// The files under each prefix we are listing, sorted by key
+	listings: Arc<Mutex<HashMap<String, Arc<[(String, ObjectMeta)]>>>>,
+}
+
+impl DirectorySource {
+	pub fn new(root: impl Into<PathBuf>) -> Self {
+		Self {
+			root: root.into(),
+			page_size: 1000,
+			listings: Arc::default(),
+		}
+	}
+
+	/// Return at most `page_size` objects per page. The default is 1000.
+	pub fn with_page_size(mut self, page_size: usize) -> Self {
+		self.page_size = page_size;
+		self
+	}
+
+	/// Add every file below `dir` with a key starting with `prefix` to `out`
+	fn walk(
+		dir: &Path,
+		key: &str,
+		prefix: &str,
+		out: &mut Vec<(String, ObjectMeta)>,
+	) -> std::io::Result<()> {
+		let entries = match std::fs::read_dir(dir) {
+			Ok(x) => x,
+			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+			Err(e) => return Err(e),
+		};
+
+		for entry in entries {
+			let entry = entry?;
+			let name = entry.file_name();
+			let name = name.to_str().ok_or_else(|| {
+				std::io::Error::new(ErrorKind::InvalidData, "file name is not valid utf-8")
+			})?;
+
+			let key = if key.is_empty() {
+				name.to_owned()
+			} else {
+				format!("{key}/{name}")
+			};
+
+			let file_type = entry.file_type()?;
+			if file_type.is_dir() {
+				// Only descend into directories that may hold our prefix
+				if key.starts_with(prefix) || prefix.starts_with(&key) {
+					Self::walk(&entry.path(), &key, prefix, out)?;
+				}
+			} else if key.starts_with(prefix) {
+				let meta = std::fs::metadata(entry.path())?;
+				out.push((
+					key,
+					ObjectMeta {
+						size: Some(meta.len()),
+						last_modified: meta.modified().ok(),
+						etag: None,
+					},
+				));
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl ListingSource for DirectorySource {
+	type Error = std::io::Error;
+
+	async fn list_page(
+		&self,
+		prefix: &str,
+		token: Option<&str>,
+	) -> Result<ListingPage, Self::Error> {
+		// Nothing panics while these locks are held, so they can't be poisoned
+		let listings = || self.listings.lock().unwrap_or_else(|x| x.into_inner());
+
+		// The first page of a listing always walks the directory again
+		let cached = token.and_then(|_| listings().get(prefix).cloned());
+		let objects = match cached {
+			Some(x) => x,
+			None => {
+				let root = self.root.clone();
+				let owned_prefix = prefix.to_owned();
+				let objects = tokio::task::spawn_blocking(move || {
+					let mut objects = Vec::new();
+					Self::walk(&root, "", &owned_prefix, &mut objects)?;
+					objects.sort_by(|a, b| a.0.cmp(&b.0));
+					Ok::<_, std::io::Error>(objects)
+				})
+				.await
+				.map_err(std::io::Error::other)??;
+
+				let objects: Arc<[_]> = objects.into();
+				listings().insert(prefix.to_owned(), objects.clone());
+				objects
+			}
+		};
+
+		let page = paginate(&objects, token, self.page_size);
+		if page.next_token.is_none() {
+			listings().remove(prefix);
+		}
+
+		Ok(page)
+	}
+}
+
+//
+// MARK: fixtures
+//
+
+/// A [ListingSource] that replays recorded `ListObjectsV2` responses.
+///
+/// Each request is answered with the page that has the same prefix
+/// and continuation token.
+#[cfg(feature = "s3")]
+#[derive(Debug, Clone)]
+pub struct ListObjectsV2Source {
+	pages: Vec<crate::ListObjectsV2Page>,
+}
+
+#[cfg(feature = "s3")]
+impl ListObjectsV2Source {
+	pub fn new(pages: Vec<crate::ListObjectsV2Page>) -> Self {
+		Self { pages }
+	}
+
+	/// Read recorded pages from xml files
+	pub fn from_files<P: AsRef<Path>, I: IntoIterator<Item = P>>(
+		pages: I,
+	) -> Result<Self, crate::S3ParseError> {
+		let pages = pages
+			.into_iter()
+			.map(|p| {
+				let file = std::io::BufReader::new(std::fs::File::open(p)?);
+				crate::ListObjectsV2Page::from_reader(file)
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		Ok(Self::new(pages))
+	}
+}
+
+#[cfg(feature = "s3")]
+impl ListingSource for ListObjectsV2Source {
+	type Error = std::io::Error;
+
+	async fn list_page(
+		&self,
+		prefix: &str,
+		token: Option<&str>,
+	) -> Result<ListingPage, Self::Error> {
+		let page = self
+			.pages
+			.iter()
+			.find(|p| {
+				p.prefix.as_deref().unwrap_or("") == prefix
+					&& p.continuation_token.as_deref() == token
+			})
+			.ok_or_else(|| {
+				std::io::Error::new(
+					ErrorKind::NotFound,
+					format!("no recorded page for prefix `{prefix}` and token {token:?}"),
+				)
+			})?;
+
+		Ok(ListingPage {
+			objects: page.contents.clone(),
+			next_token: page
+				.next_continuation_token
+				.clone()
+				.filter(|_| page.is_truncated),
+		})
+	}
+}
+
+//
+// MARK: index
+//
+
+impl DatapathIndex {
+	/// Build an index by listing every prefix in `prefixes` from `source`,
+	/// following each listing's continuation tokens to its last page.
+	///
+	/// At most `concurrency` prefixes are listed at once.
+	/// Objects under more than one prefix are only stored once.
+	pub async fn from_source<L: ListingSource, S: AsRef<str>>(
+		source: &L,
+		prefixes: impl IntoIterator<Item = S>,
+		concurrency: usize,
+	) -> Result<Self, L::Error> {
+		// Owned prefixes keep borrows out of the stream,
+		// so this future is `Send` whenever the source is `Sync`.
+		let prefixes = prefixes
+			.into_iter()
+			.map(|x| x.as_ref().to_owned())
+			.collect::<Vec<_>>();
+
+		let mut listings = futures::stream::iter(prefixes)
+			.map(|prefix| Self::list_prefix(source, prefix))
+			.buffer_unordered(concurrency.max(1));
+
+		let mut index = Self::new_empty();
+		while let Some(objects) = listings.next().await {
+			for (path, meta) in objects? {
+				index.insert(&path, meta);
+			}
+		}
+
+		Ok(index)
+	}
+
+	/// List every page of `prefix` from `source`
+	async fn list_prefix<L: ListingSource>(
+		source: &L,
+		prefix: String,
+	) -> Result<Vec<(String, ObjectMeta)>, L::Error> {
+		let mut objects = Vec::new();
+		let mut token = None;
+		loop {
+			let page = source.list_page(&prefix, token.as_deref()).await?;
+			objects.extend(page.objects);
+
+			token = page.next_token;
+			if token.is_none() {
+				return Ok(objects);
+			}
+		}
+	}
+}
+
+//
+// MARK: tests
+//
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod source_tests {
+	use super::*;
+
+	fn paths(index: &DatapathIndex) -> Vec<String> {
+		index.query("**").unwrap().collect()
+	}
+
+	#[tokio::test]
+	async fn vec_source() {
+		let source = VecSource::new(
+			["web/a", "web/b", "web/c", "api/a", "api/b", "other/a"]
+				.map(|x| (x, ObjectMeta::default())),
+		)
+		.with_page_size(2);
+
+		let page = source.list_page("web/", None).await.unwrap();
+		assert_eq!(page.objects.len(), 2);
+		assert_eq!(page.next_token.as_deref(), Some("web/b"));
+
+		let page = source.list_page("web/", Some("web/b")).await.unwrap();
+		assert_eq!(page.objects.len(), 1);
+		assert_eq!(page.next_token, None);
+
+		let index = DatapathIndex::from_source(&source, ["web/", "api/", "web/b"], 2)
+			.await
+			.unwrap();
+		assert_eq!(
+			paths(&index),
+			vec!["api/a", "api/b", "web/a", "web/b", "web/c"]
+		);
+	}
+
+	#[tokio::test]
+	async fn directory_source() {
+		let root = std::env::temp_dir().join(format!("datapath-{}", uuid::Uuid::new_v4()));
+		for file in [
+			"web/ts=1/a.json",
+			"web/ts=2/a.json",
+			"web/ts=2/b.json",
+			"api/a.json",
+		] {
+			let path = root.join(file);
+			std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+			std::fs::write(path, "data").unwrap();
+		}
+
+		let source = DirectorySource::new(&root).with_page_size(1);
+		let index = DatapathIndex::from_source(&source, ["web/ts="], 4)
+			.await
+			.unwrap();
+		assert_eq!(
+			paths(&index),
+			vec!["web/ts=1/a.json", "web/ts=2/a.json", "web/ts=2/b.json"]
+		);
+		assert_eq!(index.get_meta("web/ts=1/a.json").unwrap().size, Some(4));
+
+		// Later pages are read from the first page's walk
+		let page = source.list_page("web/", None).await.unwrap();
+		assert_eq!(page.next_token.as_deref(), Some("web/ts=1/a.json"));
+		std::fs::write(root.join("web/ts=1/b.json"), "data").unwrap();
+		let page = source
+			.list_page("web/", page.next_token.as_deref())
+			.await
+			.unwrap();
+		assert_eq!(page.objects[0].0, "web/ts=2/a.json");
+
+		// Finished listings are dropped, and new listings see new files
+		let page = source
+			.list_page("web/", Some("web/ts=2/a.json"))
+			.await
+			.unwrap();
+		assert_eq!(page.next_token, None);
+		assert!(source.listings.lock().unwrap().is_empty());
+		let index = DatapathIndex::from_source(&source, ["web/"], 1)
+			.await
+			.unwrap();
+		assert_eq!(index.len(), 4);
+
+		let index = DatapathIndex::from_source(&source, ["missing/"], 4)
+			.await
+			.unwrap();
+		assert!(index.is_empty());
+
+		// Listings can run on other tasks
+		let index =
+			tokio::spawn(
+				async move { DatapathIndex::from_source(&source, ["web/", "api/"], 2).await },
+			)
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(index.len(), 5);
+
+		std::fs::remove_dir_all(root).unwrap();
+	}
+
+	#[cfg(feature = "s3")]
+	#[tokio::test]
+	async fn fixture_source() {
+		let page = |token: Option<&str>, next: Option<&str>, key: &str| crate::ListObjectsV2Page {
+			prefix: Some("web/".to_owned()),
+			is_truncated: next.is_some(),
+			continuation_token: token.map(|x| x.to_owned()),
+			next_continuation_token: next.map(|x| x.to_owned()),
+			contents: vec![(key.to_owned(), ObjectMeta::default())],
+			..Default::default()
+		};
+
+		let source = ListObjectsV2Source::new(vec![
+			page(None, Some("t1"), "web/a"),
+			page(Some("t1"), Some("t2"), "web/b"),
+			page(Some("t2"), None, "web/c"),
+		]);
+
+		let index = DatapathIndex::from_source(&source, ["web/"], 1)
+			.await
+			.unwrap();
+		assert_eq!(paths(&index), vec!["web/a", "web/b", "web/c"]);
+
+		assert!(
+			DatapathIndex::from_source(&source, ["api/"], 1)
+				.await
+				.is_err()
+		);
+	}
+}
@@ -0,0 +1,284 @@
+use std::{
+	borrow::Cow,
+	cmp::Ordering,
+	collections::{BTreeMap, HashSet},
+};
+
+use crate::{
+	DatapathIndex, Rule,
+	index::{range::compare_values, trie::Node},
+};
+
+/// Which segments [DatapathIndex::patterns] treats as file names.
+///
+/// File names are often unique (like `<uuid>.parquet`), so each would
+/// otherwise be its own pattern. The file name segments of a pattern
+/// are replaced with `*`, which keeps patterns valid queries.
+/// This only changes patterns: queries still match the original names.
+#[derive(Debug, Clone, Default)]
+pub enum FileNames {
+	/// Treat the last segment of each path as its file name,
+	/// unless it is a partition (like `ts=1`).
+	#[default]
+	Last,
+
+	/// Treat each segment that matches this rule as a file name
+	Matching(Rule),
+
+	/// Keep all file names in patterns
+	Keep,
+}
+
+/// Statistics for one partition key in a pattern,
+/// returned by [DatapathIndex::key_stats]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyStats {
+	/// The name of this key
+	pub key: String,
+
+	/// The number of distinct values of this key
+	pub cardinality: usize,
+
+	/// The smallest value of this key.
+	/// Values are compared like in [DatapathIndex::query_range].
+	pub min: String,
+
+	/// The largest value of this key
+	pub max: String,
+}
+
+/// The values of one key in one pattern
+#[derive(Default)]
+struct KeyValues<'a> {
+	values: HashSet<&'a str>,
+	min: &'a str,
+	max: &'a str,
+}
+
+impl<'a> KeyValues<'a> {
+	fn add(&mut self, value: &'a str) {
+		if self.values.is_empty() {
+			self.min = value;
+			self.max = value;
+		} else if compare_values(value, self.min) == Ordering::Less {
+			self.min = value;
+		} else if compare_values(value, self.max) == Ordering::Greater {
+			self.max = value;
+		}
+
+		self.values.insert(value);
+	}
+}
+
+impl DatapathIndex {
+	/// Choose which segments [Self::patterns] and [Self::key_stats]
+	/// treat as file names. The default is [FileNames::Last].
+	pub fn with_file_names(mut self, file_names: FileNames) -> Self {
+		self.file_names = file_names;
+		self
+	}
+
+	/// Get every distinct pattern in this index and the number of paths
+	/// that have it, sorted by pattern.
+	///
+	/// A path's pattern replaces each partition value and its file name
+	/// with `*`, so `web/domain=a.com/ts=1/data.json` has the pattern
+	/// `web/domain=*/ts=*/*`. See [Self::with_file_names].
+	///
+	/// This walks the whole index.
+	pub fn patterns(&self) -> impl Iterator<Item = (String, usize)> {
+		let mut counts = BTreeMap::new();
+		self.for_each_pattern(
+			&self.root,
+			&mut Vec::new(),
+			&mut Vec::new(),
+			&mut |pattern, _| {
+				*counts.entry(pattern.join("/")).or_insert(0) += 1;
+			},
+		);
+
+		counts.into_iter()
+	}
+
+	/// Get the cardinality, min, and max of each partition key in each
+	/// pattern in this index (see [Self::patterns]), sorted by pattern.
+	/// Keys are in the order they appear in their pattern.
+	///
+	/// This walks the whole index.
+	pub fn key_stats(&self) -> impl Iterator<Item = (String, Vec<KeyStats>)> {
+		let mut patterns: BTreeMap<String, Vec<(&str, KeyValues<'_>)>> = BTreeMap::new();
+		self.for_each_pattern(
+			&self.root,
+			&mut Vec::new(),
+			&mut Vec::new(),
+			&mut |pattern, values| {
+				let keys = patterns.entry(pattern.join("/")).or_default();
+				for (key, value) in values {
+					match keys.iter_mut().find(|(k, _)| k == key) {
+						Some((_, x)) => x.add(value),
+						None => {
+							let mut x = KeyValues::default();
+							x.add(value);
+							keys.push((key, x));
+						}
+					}
+				}
+			},
+		);
+
+		patterns.into_iter().map(|(pattern, keys)| {
+			let keys = keys
+				.into_iter()
+				.map(|(key, values)| KeyStats {
+					key: key.to_owned(),
+					cardinality: values.values.len(),
+					min: values.min.to_owned(),
+					max: values.max.to_owned(),
+				})
+				.collect();
+			(pattern, keys)
+		})
+	}
+
+	/// Call `f` with the pattern and partition values of every path below `node`.
+	/// `pattern` and `values` hold the segments above `node`.
+	fn for_each_pattern<'a>(
+		&self,
+		node: &'a Node,
+		pattern: &mut Vec<Cow<'a, str>>,
+		values: &mut Vec<(&'a str, &'a str)>,
+		f: &mut impl FnMut(&[Cow<'a, str>], &[(&'a str, &'a str)]),
+	) {
+		if node.object().is_some() {
+			// The last segment is a file name if it isn't a partition.
+			// Partitions were replaced with `key=*` when we pushed them.
+			let is_file = matches!(self.file_names, FileNames::Last)
+				&& matches!(pattern.last(), Some(Cow::Borrowed(_)));
+
+			if is_file && let Some(last) = pattern.pop() {
+				pattern.push(Cow::Borrowed("*"));
+				f(pattern, values);
+				pattern.pop();
+				pattern.push(last);
+			} else {
+				f(pattern, values);
+			}
+		}
+
+		for (seg, child) in node.children.iter() {
+			let pushed = match seg.split_once('=') {
+				Some((key, value)) => {
+					pattern.push(Cow::Owned(format!("{key}=*")));
+					values.push((key, value));
+					true
+				}
+				None => match &self.file_names {
+					FileNames::Matching(rule) if rule.is_match(seg) => {
+						pattern.push(Cow::Borrowed("*"));
+						false
+					}
+					_ => {
+						pattern.push(Cow::Borrowed(seg));
+						false
+					}
+				},
+			};
+
+			self.for_each_pattern(child, pattern, values, f);
+
+			pattern.pop();
+			if pushed {
+				values.pop();
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod stats_tests {
+	use super::*;
+
+	#[test]
+	fn patterns() {
+		let paths = vec![
+			"web/domain=a.com/ts=1/data.json",
+			"web/domain=a.com/ts=2/data.json",
+			"web/domain=b.com/ts=10/data.json",
+			"web/domain=b.com/ts=9/data.json",
+			"web/domain=b.com/data.json",
+			"web",
+		];
+		let idx = DatapathIndex::new(paths.into_iter()).with_file_names(FileNames::Keep);
+
+		assert_eq!(
+			idx.patterns().collect::<Vec<_>>(),
+			vec![
+				("web".to_owned(), 1),
+				("web/domain=*/data.json".to_owned(), 1),
+				("web/domain=*/ts=*/data.json".to_owned(), 4),
+			]
+		);
+
+		let stats = idx.key_stats().collect::<BTreeMap<_, _>>();
+		assert_eq!(stats.len(), 3);
+		assert!(stats["web"].is_empty());
+
+		let keys = &stats["web/domain=*/ts=*/data.json"];
+		assert_eq!(
+			keys,
+			&vec![
+				KeyStats {
+					key: "domain".to_owned(),
+					cardinality: 2,
+					min: "a.com".to_owned(),
+					max: "b.com".to_owned(),
+				},
+				KeyStats {
+					key: "ts".to_owned(),
+					cardinality: 4,
+					min: "1".to_owned(),
+					max: "10".to_owned(),
+				},
+			]
+		);
+
+		let keys = stats.get("web/domain=*/data.json").unwrap();
+		assert_eq!(keys.len(), 1);
+		assert_eq!(keys[0].cardinality, 1);
+		assert_eq!(keys[0].min, "b.com");
+
+		assert_eq!(DatapathIndex::new_empty().patterns().count(), 0);
+	}
+
+	#[test]
+	fn file_names() {
+		let paths = (0..100)
+			.map(|i| format!("web/ts={}/{i:08x}.parquet", i % 3))
+			.chain(["web/ts=1/_SUCCESS".to_owned(), "web/ts=2".to_owned()])
+			.collect::<Vec<_>>();
+
+		let idx = DatapathIndex::new(paths.iter().cloned());
+		assert_eq!(
+			idx.patterns().collect::<Vec<_>>(),
+			vec![("web/ts=*".to_owned(), 1), ("web/ts=*/*".to_owned(), 101)]
+		);
+
+		// File names are still matched
+		assert_eq!(idx.query("web/*/_SUCCESS").unwrap().count(), 1);
+		assert_eq!(idx.query("web/*/*.parquet").unwrap().count(), 100);
+
+		let idx = idx.with_file_names(FileNames::Matching(Rule::new("*.parquet").unwrap()));
+		assert_eq!(
+			idx.patterns().collect::<Vec<_>>(),
+			vec![
+				("web/ts=*".to_owned(), 1),
+				("web/ts=*/*".to_owned(), 100),
+				("web/ts=*/_SUCCESS".to_owned(), 1),
+			]
+		);
+
+		let idx = idx.with_file_names(FileNames::Keep);
+		assert_eq!(idx.patterns().count(), 102);
+	}
+}
@@ -0,0 +1,200 @@
+use futures::{Stream, StreamExt};
+use std::{borrow::Borrow, pin::pin, task::Poll};
+use tracing::trace;
+
+use crate::{DatapathIndex, ObjectMeta, Rule, index::query::Matches};
+
+/// Insert this many paths at a time before yielding to the runtime
+const YIELD_EVERY: usize = 4096;
+
+/// Visit at most this many trie nodes per chunk of a query stream
+/// before yielding to the runtime
+const VISIT_BUDGET: usize = 4096;
+
+/// Yield to the async runtime once, so that other tasks can run.
+/// This works with any runtime.
+async fn yield_now() {
+	let mut yielded = false;
+	std::future::poll_fn(|cx| {
+		if yielded {
+			return Poll::Ready(());
+		}
+
+		yielded = true;
+		cx.waker().wake_by_ref();
+		Poll::Pending
+	})
+	.await
+}
+
+impl DatapathIndex {
+	/// Create an index of the paths in a stream.
+	/// Duplicate paths are only stored once.
+	pub async fn from_stream<S: Into<String>>(paths: impl Stream<Item = S>) -> Self {
+		Self::from_stream_with_meta(paths.map(|s| (s, ObjectMeta::default()))).await
+	}
+
+	/// Like [Self::from_stream], but attaches [ObjectMeta] to each path.
+	/// If a path is given more than once, its last metadata is kept.
+	pub async fn from_stream_with_meta<S: Into<String>>(
+		objects: impl Stream<Item = (S, ObjectMeta)>,
+	) -> Self {
+		let mut objects = pin!(objects);
+		let mut index = Self::new_empty();
+
+		let mut n = 0usize;
+		while let Some((s, meta)) = objects.next().await {
+			index.insert(&s.into(), meta);
+
+			n += 1;
+			if n.is_multiple_of(YIELD_EVERY) {
+				yield_now().await;
+			}
+		}
+
+		index
+	}
+
+	/// Like [Self::query], but returns a stream of results.
+	///
+	/// Results are returned in chunks of at most `chunk_size` paths,
+	/// and this stream yields to the runtime between chunks so that
+	/// a huge query doesn't block other tasks. Chunks end early if we visit
+	/// too many paths that don't match, so a query with few matches in a huge
+	/// index doesn't block other tasks either.
+	///
+	/// Returns `None` if the query was invalid.
+	pub fn query_stream(
+		&self,
+		query: impl Into<String>,
+		chunk_size: usize,
+	) -> Option<impl Stream<Item = Vec<String>> + '_> {
+		let rule = Rule::new(query)?;
+		Some(self.chunks(rule, chunk_size))
+	}
+
+	/// Like [Self::query_stream], but with a precompiled rule
+	pub fn query_rule_stream<'a>(
+		&'a self,
+		rule: &'a Rule,
+		chunk_size: usize,
+	) -> impl Stream<Item = Vec<String>> + 'a {
+		self.chunks(rule, chunk_size)
+	}
+
+	fn chunks<'a, R: Borrow<Rule> + 'a>(
+		&'a self,
+		rule: R,
+		chunk_size: usize,
+	) -> impl Stream<Item = Vec<String>> + 'a {
+		trace!("DatapathIndex query is {}", rule.borrow().pattern());
+
+		let matches = Matches::new(&self.root, self.matcher(rule));
+		futures::stream::unfold((matches, false), move |(mut matches, started)| async move {
+			if started {
+				yield_now().await;
+			}
+
+			loop {
+				let mut chunk = Vec::new();
+				let stop = matches.visited().saturating_add(VISIT_BUDGET);
+				let done = loop {
+					let budget = stop.saturating_sub(matches.visited());
+					match matches.next_within(budget) {
+						Some(Some((path, _))) => {
+							chunk.push(path.to_string());
+							if chunk.len() >= chunk_size.max(1) {
+								break false;
+							}
+						}
+						Some(None) => break true,
+						None => break false,
+					}
+				};
+
+				if !chunk.is_empty() {
+					return Some((chunk, (matches, true)));
+				} else if done {
+					return None;
+				}
+
+				// Nothing matched within our budget,
+				// so let other tasks run before we look further.
+				yield_now().await;
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used)]
+mod stream_tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn from_stream() {
+		let paths = (0..10_000).map(|i| format!("web/ts={}/a.json", i % 5000));
+		let idx = DatapathIndex::from_stream(futures::stream::iter(paths)).await;
+		assert_eq!(idx.len(), 5000);
+
+		let (tx, rx) = tokio::sync::mpsc::channel(16);
+		let send = tokio::spawn(async move {
+			for p in ["a/b", "a/c", "a/b"] {
+				tx.send(p).await.unwrap();
+			}
+		});
+		let idx = DatapathIndex::async_new(rx).await;
+		send.await.unwrap();
+		assert_eq!(idx.len(), 2);
+	}
+
+	#[tokio::test]
+	async fn query_stream() {
+		let idx = DatapathIndex::new((0..25).map(|i| format!("web/ts={i:02}/a.json")));
+
+		let chunks = idx
+			.query_stream("web/ts=*/a.json", 10)
+			.unwrap()
+			.collect::<Vec<_>>()
+			.await;
+		assert_eq!(
+			chunks.iter().map(|x| x.len()).collect::<Vec<_>>(),
+			vec![10, 10, 5]
+		);
+
+		let streamed = chunks.concat();
+		let expected = idx.query("web/ts=*/a.json").unwrap().collect::<Vec<_>>();
+		assert_eq!(streamed, expected);
+
+		let rule = Rule::new("api/**").unwrap();
+		assert_eq!(idx.query_rule_stream(&rule, 10).count().await, 0);
+		assert!(idx.query_stream("web/***", 10).is_none());
+	}
+
+	#[tokio::test]
+	async fn query_stream_budget() {
+		let mut idx = DatapathIndex::new((0..10_000).map(|i| format!("web/ts={i:05}/a.json")));
+
+		// Chunks end when we run out of nodes to visit
+		let chunks = idx
+			.query_stream("web/**", 100_000)
+			.unwrap()
+			.collect::<Vec<_>>()
+			.await;
+		assert!(chunks.len() > 1);
+		assert!(chunks.iter().all(|x| x.len() <= VISIT_BUDGET));
+		assert_eq!(
+			chunks.concat(),
+			idx.query("web/**").unwrap().collect::<Vec<_>>()
+		);
+
+		// Budgets that run out without a match don't end the stream
+		idx.insert("web/ts=09999/b.json", ObjectMeta::default());
+		let chunks = idx
+			.query_stream("web/*/b.json", 10)
+			.unwrap()
+			.collect::<Vec<_>>()
+			.await;
+		assert_eq!(chunks, vec![vec!["web/ts=09999/b.json".to_owned()]]);
+	}
+}
@@ -0,0 +1,122 @@
+//! Datapaths for tests.
+//!
+//! These implement [Datapath] by hand,
+//! since `datapath!` can't be used in this crate.
+
+use std::fmt::Display;
+
+use crate::{Datapath, DatapathFile, Wildcardable};
+
+/// `capture/user_id=String/ts=i64/raw`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CaptureRaw {
+	pub user_id: String,
+	pub ts: i64,
+}
+
+impl Display for CaptureRaw {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "capture/user_id={}/ts={}/raw", self.user_id, self.ts)
+	}
+}
+
+impl Datapath for CaptureRaw {
+	const PATTERN: &'static str = "capture/user_id=String/ts=i64/raw";
+	type Tuple = (String, i64);
+	type WildcardableTuple = (Wildcardable<String>, Wildcardable<i64>);
+
+	fn from_tuple((user_id, ts): Self::Tuple) -> Self {
+		Self { user_id, ts }
+	}
+
+	fn to_tuple(self) -> Self::Tuple {
+		(self.user_id, self.ts)
+	}
+
+	fn from_wildcardable((user_id, ts): Self::WildcardableTuple) -> String {
+		format!("capture/user_id={user_id}/ts={ts}/raw")
+	}
+
+	fn with_file(&self, file: impl Into<String>) -> DatapathFile<Self> {
+		DatapathFile {
+			path: self.clone(),
+			file: file.into(),
+		}
+	}
+
+	fn parse(path: &str) -> Option<DatapathFile<Self>> {
+		let mut parts = path.splitn(5, '/');
+		if parts.next()? != "capture" {
+			return None;
+		}
+		let user_id = parts.next()?.strip_prefix("user_id=")?.to_owned();
+		let ts = parts.next()?.strip_prefix("ts=")?.parse().ok()?;
+		if parts.next()? != "raw" {
+			return None;
+		}
+
+		Some(Self { user_id, ts }.with_file(parts.next().unwrap_or("")))
+	}
+
+	fn field(&self, name: &str) -> Option<String> {
+		match name {
+			"user_id" => Some(self.user_id.clone()),
+			"ts" => Some(self.ts.to_string()),
+			_ => None,
+		}
+	}
+}
+
+/// `web/domain=String`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct WebPage {
+	pub domain: String,
+}
+
+impl Display for WebPage {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "web/domain={}", self.domain)
+	}
+}
+
+impl Datapath for WebPage {
+	const PATTERN: &'static str = "web/domain=String";
+	type Tuple = (String,);
+	type WildcardableTuple = (Wildcardable<String>,);
+
+	fn from_tuple((domain,): Self::Tuple) -> Self {
+		Self { domain }
+	}
+
+	fn to_tuple(self) -> Self::Tuple {
+		(self.domain,)
+	}
+
+	fn from_wildcardable((domain,): Self::WildcardableTuple) -> String {
+		format!("web/domain={domain}")
+	}
+
+	fn with_file(&self, file: impl Into<String>) -> DatapathFile<Self> {
+		DatapathFile {
+			path: self.clone(),
+			file: file.into(),
+		}
+	}
+
+	fn parse(path: &str) -> Option<DatapathFile<Self>> {
+		let mut parts = path.splitn(3, '/');
+		if parts.next()? != "web" {
+			return None;
+		}
+		let domain = parts.next()?.strip_prefix("domain=")?.to_owned();
+
+		Some(Self { domain }.with_file(parts.next().unwrap_or("")))
+	}
+
+	fn field(&self, name: &str) -> Option<String> {
+		match name {
+			"domain" => Some(self.domain.clone()),
+			_ => None,
+		}
+	}
+}
@@ -0,0 +1,463 @@
+use std::{
+	collections::{BTreeMap, HashSet, btree_map},
+	mem::size_of,
+	ops::Bound,
+	slice,
+	sync::Arc,
+};
+
+use crate::{MemoryUsage, ObjectMeta};
+
+/// The metadata of objects we know nothing about
+const EMPTY_META: &ObjectMeta = &ObjectMeta {
+	size: None,
+	last_modified: None,
+	etag: None,
+};
+
+/// A node with no children that isn't an object
+pub(crate) static EMPTY_NODE: Node = Node {
+	children: Children::Vec(Vec::new()),
+	is_object: false,
+	has_hidden: false,
+	meta: None,
+	count: 0,
+};
+
+/// Nodes with more than this many children store them in a map
+const MAX_VEC_CHILDREN: usize = 32;
+
+/// Returns `true` if this is a hidden segment,
+/// like `_SUCCESS` or `.hive-staging`.
+pub(crate) fn is_hidden(segment: &str) -> bool {
+	segment.starts_with('_') || segment.starts_with('.')
+}
+
+//
+// MARK: interner
+//
+
+/// The distinct path segments in a trie.
+///
+/// Most segments repeat under many parents (`ts=1234`, `data.json`),
+/// so every edge with the same segment shares one allocation.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Interner {
+	segments: HashSet<Arc<str>>,
+}
+
+impl Interner {
+	/// Get the shared copy of `segment`
+	pub fn intern(&mut self, segment: &str) -> Arc<str> {
+		if let Some(x) = self.segments.get(segment) {
+			return x.clone();
+		}
+
+		let x: Arc<str> = segment.into();
+		self.segments.insert(x.clone());
+		return x;
+	}
+
+	/// Returns `true` if an edge uses `segment`
+	pub fn contains(&self, segment: &str) -> bool {
+		self.segments.contains(segment)
+	}
+
+	/// Forget `segment` if no edge uses it anymore
+	fn release(&mut self, segment: Arc<str>) {
+		// One reference in this set, and the one we were given
+		if Arc::strong_count(&segment) == 2
+			&& self
+				.segments
+				.get(&*segment)
+				.is_some_and(|x| Arc::ptr_eq(x, &segment))
+		{
+			self.segments.remove(&*segment);
+		}
+	}
+
+	pub fn memory_usage(&self, usage: &mut MemoryUsage) {
+		usage.segments += self.segments.len();
+		usage.segment_bytes += self.segments.capacity() * (size_of::<Arc<str>>() + 1);
+		for x in &self.segments {
+			// Arcs store two reference counts before their data
+			usage.segment_bytes += 2 * size_of::<usize>() + x.len();
+		}
+	}
+}
+
+//
+// MARK: node
+//
+
+/// A node in a [crate::DatapathIndex]'s segment trie.
+///
+/// Each edge is one `/`-separated segment of a path,
+/// with real partition values (`domain=example.com`, not `domain=*`).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Node {
+	/// Child nodes, keyed by path segment
+	pub children: Children,
+
+	/// If `true`, a path ends at this node
+	is_object: bool,
+
+	/// If `true`, a path below this node has a hidden segment after this node
+	has_hidden: bool,
+
+	/// The metadata of the path that ends here.
+	/// Empty metadata isn't stored.
+	meta: Option<Box<ObjectMeta>>,
+
+	/// The number of paths that end at or below this node.
+	/// We keep this small, since there is one in every node,
+	/// so an index can't hold more than `u32::MAX` paths.
+	count: u32,
+}
+
+impl Node {
+	/// Count `n` more paths at or below this node
+	fn add_count(&mut self, n: u32) {
+		// Panicking is better than silently wrapping, which breaks counts and removal
+		#[expect(clippy::expect_used)]
+		let count = self
+			.count
+			.checked_add(n)
+			.expect("a DatapathIndex can't hold more than u32::MAX paths");
+		self.count = count;
+	}
+
+	/// The number of paths that end at or below this node
+	pub fn count(&self) -> usize {
+		self.count as usize
+	}
+
+	/// Returns `true` if a path below this node
+	/// has a hidden segment (see [is_hidden]) after this node
+	pub fn has_hidden(&self) -> bool {
+		self.has_hidden
+	}
+
+	/// If a path ends at this node, get its metadata
+	pub fn object(&self) -> Option<&ObjectMeta> {
+		self.is_object
+			.then(|| self.meta.as_deref().unwrap_or(EMPTY_META))
+	}
+
+	/// Insert the path with the given segments below this node.
+	/// If it already exists, replace its metadata.
+	///
+	/// Returns `true` if this path is new.
+	pub fn insert(&mut self, segments: &[&str], meta: ObjectMeta, interner: &mut Interner) -> bool {
+		let is_new = match segments.split_first() {
+			None => {
+				let is_new = !self.is_object;
+				self.is_object = true;
+				self.meta = (!meta.is_empty()).then(|| Box::new(meta));
+				is_new
+			}
+
+			Some((first, rest)) => self
+				.children
+				.get_or_insert(first, interner)
+				.insert(rest, meta, interner),
+		};
+
+		if is_new {
+			self.add_count(1);
+			self.has_hidden |= segments.iter().any(|x| is_hidden(x));
+		}
+
+		return is_new;
+	}
+
+	/// Add every path below `other` to this node.
+	/// Paths that are in both keep the metadata from `other`.
+	///
+	/// Every edge taken from `other` is interned with `interner`,
+	/// so that its segments are shared with the rest of this trie.
+	#[cfg(feature = "rayon")]
+	pub fn merge(&mut self, other: Node, interner: &mut Interner) {
+		if other.is_object {
+			if !self.is_object {
+				self.add_count(1);
+			}
+			self.is_object = true;
+			self.meta = other.meta;
+		}
+
+		for (seg, child) in other.children.into_entries() {
+			let mine = self.children.get_or_insert(&seg, interner);
+			let before = mine.count;
+			mine.merge(child, interner);
+			let added = mine.count - before;
+			let hidden = is_hidden(&seg) || mine.has_hidden;
+			self.add_count(added);
+			self.has_hidden |= hidden;
+		}
+	}
+
+	/// Remove the path with the given segments below this node,
+	/// along with any nodes that no longer lead to a path.
+	///
+	/// Returns `true` if this path existed.
+	pub fn remove(&mut self, segments: &[&str], interner: &mut Interner) -> bool {
+		let removed = match segments.split_first() {
+			None => {
+				let removed = self.is_object;
+				self.is_object = false;
+				self.meta = None;
+				removed
+			}
+
+			Some((first, rest)) => {
+				let Some(child) = self.children.get_mut(first) else {
+					return false;
+				};
+
+				let removed = child.remove(rest, interner);
+				if removed
+					&& child.count == 0
+					&& let Some(segment) = self.children.remove(first)
+				{
+					interner.release(segment);
+				}
+				removed
+			}
+		};
+
+		if removed {
+			self.count -= 1;
+
+			// Only look for other hidden paths if we removed one
+			if self.has_hidden && segments.iter().any(|x| is_hidden(x)) {
+				self.has_hidden = self
+					.children
+					.iter()
+					.any(|(seg, child)| is_hidden(seg) || child.has_hidden);
+			}
+		}
+
+		return removed;
+	}
+
+	/// Add the memory used by this node and its children to `usage`.
+	/// `path_len` is the length of the path to this node, or `None` at the root.
+	pub fn memory_usage(&self, path_len: Option<usize>, usage: &mut MemoryUsage) {
+		usage.nodes += 1;
+
+		if self.is_object {
+			usage.uncompressed_bytes += size_of::<String>() + path_len.unwrap_or(0);
+		}
+
+		if let Some(meta) = &self.meta {
+			usage.meta_bytes += size_of::<ObjectMeta>();
+			usage.meta_bytes += meta.etag.as_ref().map(|x| x.capacity()).unwrap_or(0);
+		}
+
+		let entry = size_of::<(Arc<str>, Node)>();
+		// Children are stored inline in their parent's table
+		if path_len.is_none() {
+			usage.trie_bytes += size_of::<Node>();
+		}
+		usage.trie_bytes += match &self.children {
+			Children::Vec(x) => x.capacity() * entry,
+			// A rough estimate, btree nodes have some overhead
+			Children::Map(x) => size_of::<BTreeMap<Arc<str>, Node>>() + x.len() * entry * 3 / 2,
+		};
+
+		for (seg, child) in self.children.iter() {
+			let len = match path_len {
+				None => seg.len(),
+				Some(x) => x + 1 + seg.len(),
+			};
+			child.memory_usage(Some(len), usage);
+		}
+	}
+
+	/// Get the node at the given segments below this node
+	pub fn get(&self, segments: &[&str]) -> Option<&Node> {
+		let mut node = self;
+		for seg in segments {
+			node = node.children.get(seg)?;
+		}
+		return Some(node);
+	}
+}
+
+//
+// MARK: children
+//
+
+/// The children of a [Node], sorted by segment.
+///
+/// Most nodes have a handful of children, which we keep in a sorted
+/// vec to save memory. Nodes with many children (like a partition
+/// with thousands of values) use a map so that inserts stay fast.
+#[derive(Debug, Clone)]
+pub(crate) enum Children {
+	Vec(Vec<(Arc<str>, Node)>),
+	// Boxed so that this enum is as small as a vec
+	#[expect(clippy::box_collection)]
+	Map(Box<BTreeMap<Arc<str>, Node>>),
+}
+
+impl Default for Children {
+	fn default() -> Self {
+		Self::Vec(Vec::new())
+	}
+}
+
+impl Children {
+	pub fn len(&self) -> usize {
+		match self {
+			Self::Vec(x) => x.len(),
+			Self::Map(x) => x.len(),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	pub fn get(&self, segment: &str) -> Option<&Node> {
+		self.get_key_value(segment).map(|(_, v)| v)
+	}
+
+	pub fn get_key_value(&self, segment: &str) -> Option<(&str, &Node)> {
+		match self {
+			Self::Vec(x) => x
+				.binary_search_by(|(k, _)| (**k).cmp(segment))
+				.ok()
+				.map(|i| (&*x[i].0, &x[i].1)),
+			Self::Map(x) => x.get_key_value(segment).map(|(k, v)| (&**k, v)),
+		}
+	}
+
+	pub fn get_mut(&mut self, segment: &str) -> Option<&mut Node> {
+		match self {
+			Self::Vec(x) => x
+				.binary_search_by(|(k, _)| (**k).cmp(segment))
+				.ok()
+				.map(|i| &mut x[i].1),
+			Self::Map(x) => x.get_mut(segment),
+		}
+	}
+
+	/// Remove the child at `segment`, returning its key
+	pub fn remove(&mut self, segment: &str) -> Option<Arc<str>> {
+		match self {
+			Self::Vec(x) => x
+				.binary_search_by(|(k, _)| (**k).cmp(segment))
+				.ok()
+				.map(|i| x.remove(i).0),
+			Self::Map(x) => x.remove_entry(segment).map(|(k, _)| k),
+		}
+	}
+
+	/// Get the child at `segment`, creating an empty one if it doesn't exist
+	pub fn get_or_insert(&mut self, segment: &str, interner: &mut Interner) -> &mut Node {
+		if let Self::Vec(x) = self
+			&& x.len() >= MAX_VEC_CHILDREN
+			&& x.binary_search_by(|(k, _)| (**k).cmp(segment)).is_err()
+		{
+			*self = Self::Map(Box::new(std::mem::take(x).into_iter().collect()));
+		}
+
+		match self {
+			Self::Vec(x) => {
+				let i = match x.binary_search_by(|(k, _)| (**k).cmp(segment)) {
+					Ok(i) => i,
+					Err(i) => {
+						// Most nodes have a few children, so don't over-allocate
+						x.reserve_exact(1);
+						x.insert(i, (interner.intern(segment), Node::default()));
+						i
+					}
+				};
+				&mut x[i].1
+			}
+
+			Self::Map(x) => x.entry(interner.intern(segment)).or_default(),
+		}
+	}
+
+	/// Take all children, in order
+	#[cfg(feature = "rayon")]
+	pub fn into_entries(self) -> Vec<(Arc<str>, Node)> {
+		match self {
+			Self::Vec(x) => x,
+			Self::Map(x) => x.into_iter().collect(),
+		}
+	}
+
+	/// Iterate over all children, in order
+	pub fn iter(&self) -> ChildIter<'_> {
+		match self {
+			Self::Vec(x) => ChildIter::Vec(x.iter()),
+			Self::Map(x) => ChildIter::Map(x.range::<str, _>(..)),
+		}
+	}
+
+	/// Iterate over all children with segments `>= start`, in order
+	pub fn iter_from(&self, start: &str) -> ChildIter<'_> {
+		match self {
+			Self::Vec(x) => {
+				let i = x.partition_point(|(k, _)| &**k < start);
+				ChildIter::Vec(x[i..].iter())
+			}
+			Self::Map(x) => {
+				ChildIter::Map(x.range::<str, _>((Bound::Included(start), Bound::Unbounded)))
+			}
+		}
+	}
+}
+
+/// An iterator over the children of a [Node]
+#[derive(Debug, Clone)]
+pub(crate) enum ChildIter<'a> {
+	Vec(slice::Iter<'a, (Arc<str>, Node)>),
+	Map(btree_map::Range<'a, Arc<str>, Node>),
+}
+
+impl<'a> Iterator for ChildIter<'a> {
+	type Item = (&'a str, &'a Node);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		match self {
+			Self::Vec(x) => x.next().map(|(k, v)| (&**k, v)),
+			Self::Map(x) => x.next().map(|(k, v)| (&**k, v)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod trie_tests {
+	use super::*;
+
+	/// A node that counts as many paths as it can
+	fn full(interner: &mut Interner) -> Node {
+		let mut node = Node::default();
+		node.insert(&["a"], ObjectMeta::default(), interner);
+		node.count = u32::MAX;
+		node
+	}
+
+	#[test]
+	fn full_count() {
+		let mut interner = Interner::default();
+		let mut node = full(&mut interner);
+
+		// Replacing a path doesn't count it again
+		assert!(!node.insert(&["a"], ObjectMeta::default(), &mut interner));
+		assert_eq!(node.count(), u32::MAX as usize);
+	}
+
+	#[test]
+	#[should_panic(expected = "u32::MAX")]
+	fn count_overflow() {
+		let mut interner = Interner::default();
+		let mut node = full(&mut interner);
+		node.insert(&["b"], ObjectMeta::default(), &mut interner);
+	}
+}
@@ -7,9 +7,17 @@
 #[cfg(test)]
 use uuid as _;
 
+// silence linter, used in benches
+#[cfg(test)]
+use criterion as _;
+
+// silence linter, used by fns in index.rs and in tests
+#[cfg(any(test, feature = "tokio"))]
+use tokio as _;
+
 // silence linter, used by fns in index.rs
 #[cfg(feature = "tokio")]
-use tokio as _;
+use futures as _;
 
 mod datapath;
 pub use datapath::*;
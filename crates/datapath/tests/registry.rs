@@ -0,0 +1,52 @@
+//! Classify paths with datapaths made by `datapath!`,
+//! since the library's own tests can't use the macro.
+
+// tests only use some of datapath's dependencies
+#![expect(unused_crate_dependencies)]
+
+use datapath::{Datapath, DatapathIndex, DatapathRegistry, datapath};
+
+datapath! {
+	struct Capture(capture/user_id=String/ts=i64/raw/2.0);
+	struct Event(events/user_id=String/"v1.0");
+}
+
+#[test]
+fn classify() {
+	let paths = vec![
+		"capture/user_id=a/ts=1/raw/2.0/data.json",
+		"capture/user_id=a/ts=1/raw/2.0/_SUCCESS",
+		"capture/user_id=a/ts=x/raw/2.0/data.json",
+		"events/user_id=b/v1.0/data.json",
+		"events/user_id=b/v2.0/data.json",
+	];
+	let idx = DatapathIndex::new(paths.into_iter());
+
+	let mut registry = DatapathRegistry::new();
+	registry.register::<Capture>().register::<Event>();
+	let result = registry.classify(&idx);
+
+	let capture = result.files::<Capture>();
+	assert_eq!(capture.len(), 1);
+	assert_eq!(capture[0].path.ts, 1);
+	assert_eq!(capture[0].file, "data.json");
+
+	let events = result.files::<Event>();
+	assert_eq!(events.len(), 1);
+	assert_eq!(events[0].path.user_id, "b");
+
+	assert_eq!(
+		result.unmatched(),
+		&[
+			"capture/user_id=a/ts=1/raw/2.0/_SUCCESS",
+			"capture/user_id=a/ts=x/raw/2.0/data.json",
+			"events/user_id=b/v2.0/data.json",
+		]
+	);
+}
+
+#[test]
+fn patterns() {
+	assert_eq!(Capture::PATTERN, "capture/user_id=String/ts=i64/raw/2.0");
+	assert_eq!(Event::PATTERN, "events/user_id=String/v1.0");
+}